@@ -1,8 +1,9 @@
 use std::fs;
 use std::io::Read;
 
-use bzip2::read::BzDecoder;
-use bzip2_rs::decoder::DecoderReader;
+use bzip2::read::{BzDecoder, BzEncoder};
+use bzip2::Compression;
+use bzip2_rs::decoder::{DecoderOptions, DecoderReader};
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
 fn bench_decode(c: &mut Criterion) {
@@ -26,6 +27,32 @@ fn bench_decode(c: &mut Criterion) {
         })
     });
 
+    c.bench_function("decompress rust", move |b| {
+        b.iter(|| {
+            let compressed = black_box(compressed);
+
+            let out = bzip2_rs::decompress(compressed).unwrap();
+
+            let decompressed = black_box(decompressed);
+            assert_eq!(decompressed, out.as_slice());
+        })
+    });
+
+    c.bench_function("decode rust without crc verification", move |b| {
+        b.iter(|| {
+            let compressed = black_box(compressed);
+
+            let options = DecoderOptions::new().verify_crc(false);
+            let mut decoder = DecoderReader::with_options(compressed, options);
+
+            let mut out = Vec::with_capacity(decompressed.len());
+            decoder.read_to_end(&mut out).unwrap();
+
+            let decompressed = black_box(decompressed);
+            assert_eq!(decompressed, out.as_slice());
+        })
+    });
+
     c.bench_function("decode c", move |b| {
         b.iter(|| {
             let compressed = black_box(compressed);
@@ -41,5 +68,35 @@ fn bench_decode(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, bench_decode);
+fn bench_decode_big_block(c: &mut Criterion) {
+    let mut decompressed = Vec::new();
+    for sample in &["sample1", "sample2", "sample3"] {
+        decompressed.extend(fs::read(format!("tests/samplefiles/{}.ref", sample)).unwrap());
+    }
+
+    // a single block, as big as the whole input
+    let mut compressed = Vec::new();
+    BzEncoder::new(decompressed.as_slice(), Compression::best())
+        .read_to_end(&mut compressed)
+        .unwrap();
+
+    let compressed: &[u8] = compressed.as_ref();
+    let decompressed: &[u8] = decompressed.as_ref();
+
+    c.bench_function("decode rust big block", move |b| {
+        b.iter(|| {
+            let compressed = black_box(compressed);
+
+            let mut decoder = DecoderReader::new(compressed);
+
+            let mut out = Vec::with_capacity(decompressed.len());
+            decoder.read_to_end(&mut out).unwrap();
+
+            let decompressed = black_box(decompressed);
+            assert_eq!(decompressed, out.as_slice());
+        })
+    });
+}
+
+criterion_group!(benches, bench_decode, bench_decode_big_block);
 criterion_main!(benches);
@@ -0,0 +1,32 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use std::io::Read;
+
+use bzip2::read::BzDecoder;
+use bzip2_rs::encoder::{Encoder, ReadState};
+use bzip2_rs::header::Header;
+
+fuzz_target!(|data: &[u8]| {
+    let mut encoder = Encoder::new(Header::from_raw_blocksize(1).unwrap());
+    let mut compressed = Vec::new();
+
+    let mut input = data;
+    let mut buf = [0; 1024];
+    loop {
+        match encoder.read(&mut buf) {
+            ReadState::NeedsWrite => {
+                encoder.write(input);
+                input = &[];
+            }
+            ReadState::Read(n) => compressed.extend_from_slice(&buf[..n]),
+            ReadState::Eof => break,
+        }
+    }
+
+    let mut decoder = BzDecoder::new(compressed.as_slice());
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed).expect("the reference implementation failed decompressing what we compressed");
+
+    assert_eq!(data, decompressed.as_slice());
+});
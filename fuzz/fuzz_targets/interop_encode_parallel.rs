@@ -0,0 +1,20 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use std::io::{Read, Write};
+
+use bzip2::read::BzDecoder;
+use bzip2_rs::header::Header;
+use bzip2_rs::{ParallelEncoderWriter, RayonThreadPool};
+
+fuzz_target!(|data: &[u8]| {
+    let mut encoder = ParallelEncoderWriter::new(Vec::new(), Header::from_raw_blocksize(1).unwrap(), RayonThreadPool, 4);
+    encoder.write_all(data).expect("writing into a Vec can't fail");
+    let compressed = encoder.finish().expect("writing into a Vec can't fail");
+
+    let mut decoder = BzDecoder::new(compressed.as_slice());
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed).expect("the reference implementation failed decompressing what we compressed");
+
+    assert_eq!(data, decompressed.as_slice());
+});
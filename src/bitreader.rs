@@ -1,13 +1,13 @@
 use std::convert::TryInto;
-use std::mem;
 
-const USIZE_BYTES: usize = mem::size_of::<usize>();
-const USIZE_BITS: usize = USIZE_BYTES * 8;
+/// The number of bits which can be requested at once from [`BitReader::peek_bits`]
+const MAX_PEEK_BITS: u8 = 32;
 
 pub struct BitReader<'a> {
     bytes: [&'a [u8]; 2],
 
-    bits: usize,
+    // the buffered bits, starting from the most significant one
+    bits: u64,
     remaining_bits: u8,
 
     read_bits: u32,
@@ -23,72 +23,87 @@ impl<'a> BitReader<'a> {
         }
     }
 
+    pub fn read_bit(&mut self) -> Option<bool> {
+        self.read_bits(1).map(|bit| bit != 0)
+    }
+
     pub fn read_u8(&mut self, bit_count: u8) -> Option<u8> {
         debug_assert!(bit_count <= 8);
 
-        let mut read_bits = 0u8;
-        let mut value = 0;
-        for bit in self.take(usize::from(bit_count)) {
-            value = (value << 1) | u8::from(bit);
-            read_bits += 1;
-        }
-
-        if read_bits < bit_count {
-            None
-        } else {
-            Some(value)
-        }
+        self.read_bits(bit_count).map(|value| value as u8)
     }
 
     pub fn read_u16(&mut self, bit_count: u8) -> Option<u16> {
         debug_assert!(bit_count <= 16);
 
-        let mut read_bits = 0u8;
-        let mut value = 0;
-        for bit in self.take(usize::from(bit_count)) {
-            value = (value << 1) | u16::from(bit);
-            read_bits += 1;
-        }
-
-        if read_bits < bit_count {
-            None
-        } else {
-            Some(value)
-        }
+        self.read_bits(bit_count).map(|value| value as u16)
     }
 
     pub fn read_u32(&mut self, bit_count: u8) -> Option<u32> {
-        debug_assert!(bit_count <= 32);
+        self.read_bits(bit_count)
+    }
 
-        let mut read_bits = 0u8;
-        let mut value = 0;
-        for bit in self.take(usize::from(bit_count)) {
-            value = (value << 1) | u32::from(bit);
-            read_bits += 1;
+    pub fn read_u64(&mut self, bit_count: u8) -> Option<u64> {
+        debug_assert!(bit_count <= 64);
+
+        let high_bits = bit_count.saturating_sub(MAX_PEEK_BITS);
+        let high = self.read_bits(high_bits)?;
+        let low = self.read_bits(bit_count - high_bits)?;
+        Some((u64::from(high) << (bit_count - high_bits)) | u64::from(low))
+    }
+
+    /// Returns the next `bit_count` bits, without advancing the cursor
+    ///
+    /// The bits past the end of the input are returned as zeros.
+    #[inline]
+    pub fn peek_bits(&mut self, bit_count: u8) -> u32 {
+        debug_assert!(bit_count <= MAX_PEEK_BITS);
+
+        if bit_count == 0 {
+            return 0;
         }
 
-        if read_bits < bit_count {
-            None
-        } else {
-            Some(value)
+        if self.remaining_bits < bit_count {
+            self.refill_bits();
         }
+
+        (self.bits >> (64 - bit_count)) as u32
     }
 
-    pub fn read_u64(&mut self, bit_count: u8) -> Option<u64> {
-        debug_assert!(bit_count <= 64);
+    /// Advance the cursor by `bit_count` bits
+    ///
+    /// Returns `None` if less than `bit_count` bits were available,
+    /// in which case the cursor is moved to the end of the input.
+    #[inline]
+    pub fn consume(&mut self, bit_count: u8) -> Option<()> {
+        debug_assert!(bit_count <= MAX_PEEK_BITS);
+
+        if self.remaining_bits < bit_count {
+            self.refill_bits();
 
-        let mut read_bits = 0u8;
-        let mut value = 0;
-        for bit in self.take(usize::from(bit_count)) {
-            value = (value << 1) | u64::from(bit);
-            read_bits += 1;
+            if self.remaining_bits < bit_count {
+                self.bits = 0;
+                self.remaining_bits = 0;
+                return None;
+            }
         }
 
-        if read_bits < bit_count {
-            None
-        } else {
-            Some(value)
+        self.bits <<= bit_count;
+        self.remaining_bits -= bit_count;
+        Some(())
+    }
+
+    /// Advance the cursor by `bit_count` bits, which can be more than fit in the buffer
+    ///
+    /// Returns `None` if less than `bit_count` bits were available.
+    pub fn skip(&mut self, mut bit_count: u32) -> Option<()> {
+        while bit_count > 0 {
+            let chunk = bit_count.min(u32::from(MAX_PEEK_BITS)) as u8;
+            self.consume(chunk)?;
+            bit_count -= u32::from(chunk);
         }
+
+        Some(())
     }
 
     /// Returns the position of the cursor, or how many bits have been read so far.
@@ -96,64 +111,113 @@ impl<'a> BitReader<'a> {
         self.read_bits - u32::from(self.remaining_bits)
     }
 
+    #[inline]
+    fn read_bits(&mut self, bit_count: u8) -> Option<u32> {
+        let value = self.peek_bits(bit_count);
+        self.consume(bit_count)?;
+        Some(value)
+    }
+
+    /// Load as many bytes as fit into `self.bits`
+    ///
+    /// At least 56 bits are available afterwards, unless the end of the input has been reached.
     #[inline(never)]
     #[cold]
     fn refill_bits(&mut self) {
-        debug_assert_eq!(self.remaining_bits, 0);
-
-        for bytes in &mut self.bytes {
-            match bytes.get(..USIZE_BYTES) {
-                Some(chunk) => {
-                    // Fast whole `usize` fill
+        if let Some(chunk) = self.bytes[0].get(..8) {
+            // Fast whole `u64` fill
 
-                    self.bits = usize::from_be_bytes(chunk.try_into().unwrap());
-                    *bytes = &bytes[USIZE_BYTES..];
-                    self.remaining_bits = USIZE_BITS as u8;
-
-                    self.read_bits += USIZE_BITS as u32;
-                    return;
-                }
-                None if !bytes.is_empty() => {
-                    // Slower smaller than `usize` fill
+            let word = u64::from_be_bytes(chunk.try_into().unwrap());
+            let loaded_bytes = (63 - self.remaining_bits) / 8;
 
-                    let mut buf = [0u8; USIZE_BYTES];
-                    buf[..bytes.len()].copy_from_slice(bytes);
-                    self.bits = usize::from_be_bytes(buf);
+            self.bits |= word >> self.remaining_bits;
+            // only keep the bits of the bytes which have been fully loaded
+            self.bits &= !(u64::MAX >> (self.remaining_bits + loaded_bytes * 8));
+            self.bytes[0] = &self.bytes[0][usize::from(loaded_bytes)..];
+            self.remaining_bits += loaded_bytes * 8;
 
-                    let bytes_slice = mem::replace(bytes, &[]);
-                    self.remaining_bits = (bytes_slice.len() * 8) as u8;
+            self.read_bits += u32::from(loaded_bytes) * 8;
+            return;
+        }
 
-                    self.read_bits += u32::from(self.remaining_bits);
-                    return;
+        // Slower byte by byte fill, near the end of the first slice
+        while self.remaining_bits <= 56 {
+            let byte = match self.bytes[0].split_first() {
+                Some((&byte, rest)) => {
+                    self.bytes[0] = rest;
+                    byte
                 }
-                None => {
-                    // This block is empty
+                None if !self.bytes[1].is_empty() => {
+                    // continue from the second slice
+                    self.bytes = [self.bytes[1], &[]];
+                    if self.bytes[0].len() >= 8 {
+                        self.refill_bits();
+                        return;
+                    }
 
                     continue;
                 }
-            }
+                None => return,
+            };
+
+            self.bits |= u64::from(byte) << (56 - self.remaining_bits);
+            self.remaining_bits += 8;
+
+            self.read_bits += 8;
         }
     }
 }
 
-impl Iterator for BitReader<'_> {
-    type Item = bool;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_input() {
+        let bytes = (0..40u8)
+            .map(|i| i.wrapping_mul(37) ^ 0x5a)
+            .collect::<Vec<_>>();
+
+        let expected = {
+            let mut reader = BitReader::new([&bytes, &[]]);
+            (0..bytes.len() * 8)
+                .map(|_| reader.read_bit().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        for split in 0..bytes.len() {
+            let (a, b) = bytes.split_at(split);
+            let mut reader = BitReader::new([a, b]);
+
+            let mut pos = 0;
+            for &bit_count in [3u8, 17, 32, 1, 9, 20, 5].iter().cycle() {
+                if pos + usize::from(bit_count) > expected.len() {
+                    break;
+                }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.remaining_bits == 0 {
-            self.refill_bits();
+                let value = expected[pos..pos + usize::from(bit_count)]
+                    .iter()
+                    .fold(0u32, |value, &bit| (value << 1) | u32::from(bit));
+                assert_eq!(reader.peek_bits(bit_count), value);
+                assert_eq!(reader.read_u32(bit_count), Some(value));
 
-            if self.remaining_bits == 0 {
-                return None;
+                pos += usize::from(bit_count);
+                assert_eq!(reader.position() as usize, pos);
             }
-        }
 
-        // read the left most bit
-        let bit = self.bits & !(usize::MAX >> 1);
+            let remaining = (expected.len() - pos) as u32;
+            assert_eq!(reader.skip(remaining + 1), None);
+        }
+    }
 
-        self.remaining_bits -= 1;
-        self.bits <<= 1;
+    #[test]
+    fn skip() {
+        let bytes = [0xff; 20];
+        let mut reader = BitReader::new([&bytes[..3], &bytes[3..]]);
 
-        Some(bit != 0)
+        assert_eq!(reader.skip(150), Some(()));
+        assert_eq!(reader.position(), 150);
+        assert_eq!(reader.read_u16(10), Some(0x3ff));
+        assert_eq!(reader.read_u8(1), None);
     }
 }
@@ -0,0 +1,156 @@
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    // number of bytes at the start of `bytes` which have already been consumed
+    consumed: usize,
+
+    bits: u64,
+    pending_bits: u8,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            consumed: 0,
+            bits: 0,
+            pending_bits: 0,
+        }
+    }
+
+    /// Write the `bit_count` least significant bits of `value`, most significant bit first
+    pub fn write_bits(&mut self, bit_count: u8, value: u32) {
+        debug_assert!(bit_count <= 32);
+        debug_assert!(bit_count == 32 || value >> bit_count == 0);
+
+        self.bits = (self.bits << bit_count) | u64::from(value);
+        self.pending_bits += bit_count;
+
+        while self.pending_bits >= 8 {
+            self.pending_bits -= 8;
+            self.bytes.push((self.bits >> self.pending_bits) as u8);
+        }
+
+        self.bits &= !(u64::MAX << self.pending_bits);
+    }
+
+    pub fn write_bit(&mut self, bit: bool) {
+        self.write_bits(1, u32::from(bit));
+    }
+
+    pub fn write_u64(&mut self, bit_count: u8, value: u64) {
+        debug_assert!(bit_count <= 64);
+
+        if bit_count > 32 {
+            self.write_bits(bit_count - 32, (value >> 32) as u32);
+            self.write_bits(32, value as u32);
+        } else {
+            self.write_bits(bit_count, value as u32);
+        }
+    }
+
+    /// Append every bit written into `other` after the bits written into `self`
+    pub fn append(&mut self, other: &BitWriter) {
+        if self.pending_bits == 0 {
+            self.bytes.extend_from_slice(other.bytes());
+        } else {
+            for &byte in other.bytes() {
+                self.write_bits(8, u32::from(byte));
+            }
+        }
+
+        self.write_bits(other.pending_bits, other.bits as u32);
+    }
+
+    /// Pad the last byte with zeros, if any bits are pending
+    pub fn align(&mut self) {
+        if self.pending_bits > 0 {
+            self.write_bits(8 - self.pending_bits, 0);
+        }
+    }
+
+    /// Returns the fully written bytes
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[self.consumed..]
+    }
+
+    /// Removes the first `n` fully written bytes
+    ///
+    /// The memory is only reclaimed once every byte has been consumed,
+    /// or once the consumed bytes take more than half of it, so that
+    /// partially reading the bytes doesn't move the rest of them every time.
+    pub fn consume(&mut self, n: usize) {
+        debug_assert!(n <= self.bytes().len());
+
+        self.consumed += n;
+        if self.consumed == self.bytes.len() {
+            self.bytes.clear();
+            self.consumed = 0;
+        } else if self.consumed > self.bytes.len() / 2 {
+            self.bytes.drain(..self.consumed);
+            self.consumed = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitreader::BitReader;
+
+    #[test]
+    fn write_read() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(3, 0b101);
+        writer.write_u64(48, 0x314159265359);
+        writer.write_bit(true);
+        writer.write_bits(32, 0xdeadbeef);
+        writer.align();
+        assert_eq!(writer.bytes().len(), 11);
+
+        let mut reader = BitReader::new([writer.bytes(), &[]]);
+        assert_eq!(reader.read_u8(3), Some(0b101));
+        assert_eq!(reader.read_u64(48), Some(0x314159265359));
+        assert_eq!(reader.read_bit(), Some(true));
+        assert_eq!(reader.read_u32(32), Some(0xdeadbeef));
+    }
+
+    #[test]
+    fn consume() {
+        let mut writer = BitWriter::new();
+        for i in 0..100u32 {
+            writer.write_bits(8, i);
+        }
+
+        writer.consume(10);
+        assert_eq!(writer.bytes()[0], 10);
+        writer.consume(60);
+        assert_eq!(writer.bytes(), (70..100).collect::<Vec<u8>>().as_slice());
+
+        writer.write_bits(12, 0xabc);
+        writer.consume(30);
+        writer.align();
+        assert_eq!(writer.bytes(), &[0xab, 0xc0]);
+        writer.consume(2);
+        assert!(writer.bytes().is_empty());
+    }
+
+    #[test]
+    fn append_unaligned() {
+        let mut first = BitWriter::new();
+        first.write_bits(5, 0b10110);
+
+        let mut second = BitWriter::new();
+        second.write_bits(20, 0xabcde);
+        second.write_bits(3, 0b011);
+
+        first.append(&second);
+        first.align();
+        assert_eq!(first.bytes().len(), 4);
+
+        let mut reader = BitReader::new([first.bytes(), &[]]);
+        assert_eq!(reader.read_u8(5), Some(0b10110));
+        assert_eq!(reader.read_u32(20), Some(0xabcde));
+        assert_eq!(reader.read_u8(3), Some(0b011));
+        assert_eq!(reader.read_u8(4), Some(0));
+    }
+}
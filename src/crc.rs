@@ -1,42 +1,133 @@
+//! The CRC-32 used by bzip2
+//!
+//! bzip2 uses the same polynomial as the ubiquitous CRC32B, but shifts
+//! bits in MSB-first instead of LSB-first. The CRC is computed natively,
+//! through slicing-by-16 tables.
+//!
+//! With the `simd-crc` feature the CRC is instead folded through carry-less
+//! multiplication by `crc-fast`. Doing so requires Rust 1.89 and relies on the
+//! `unsafe` SIMD code of `crc-fast`, which is why it's opt-in.
+
+const POLYNOMIAL: u32 = 0x04c1_1db7;
+
+/// `TABLES[0][b]` is the CRC of the byte `b`, while `TABLES[n][b]`
+/// is the CRC of the byte `b` followed by `n` zeros
+static TABLES: [[u32; 256]; 16] = make_tables();
+
+#[cfg(not(feature = "simd-crc"))]
+pub struct Hasher {
+    crc: u32,
+}
+
+#[cfg(not(feature = "simd-crc"))]
+impl Hasher {
+    pub fn new() -> Self {
+        Self { crc: !0 }
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        self.crc = update_table(self.crc, buf);
+    }
+
+    pub fn finalize(&self) -> u32 {
+        !self.crc
+    }
+}
+
+#[cfg(feature = "simd-crc")]
 pub struct Hasher {
-    // CRC32B hasher
-    val: crc32fast::Hasher,
+    // CRC-32/BZIP2 hasher
+    digest: crc_fast::Digest,
 }
 
+#[cfg(feature = "simd-crc")]
 impl Hasher {
     pub fn new() -> Self {
+        // `crc-fast` falls back to tables when folding isn't supported
         Self {
-            val: crc32fast::Hasher::new(),
+            digest: crc_fast::Digest::new(crc_fast::CrcAlgorithm::Crc32Bzip2),
         }
     }
 
     pub fn update(&mut self, buf: &[u8]) {
-        let mut bytes = [0u8; 512];
-        let mut chunks = buf.chunks_exact(bytes.len());
+        self.digest.update(buf);
+    }
 
-        for full_chunk in &mut chunks {
-            #[allow(clippy::needless_range_loop)]
-            for i in 0..full_chunk.len() {
-                bytes[i] = full_chunk[i].reverse_bits();
-            }
+    pub fn finalize(&self) -> u32 {
+        self.digest.finalize() as u32
+    }
+}
 
-            self.val.update(&bytes);
-        }
+/// Fold the CRC of the next block into the combined CRC of the whole stream
+pub fn combine(stream_crc: u32, block_crc: u32) -> u32 {
+    stream_crc.rotate_left(1) ^ block_crc
+}
+
+// with `simd-crc` the tables are only used for comparing them in tests
+#[cfg_attr(feature = "simd-crc", allow(dead_code))]
+fn update_table(mut crc: u32, mut buf: &[u8]) -> u32 {
+    let t = &TABLES;
+
+    while buf.len() >= 16 {
+        crc = t[0x0][usize::from(buf[0xf])]
+            ^ t[0x1][usize::from(buf[0xe])]
+            ^ t[0x2][usize::from(buf[0xd])]
+            ^ t[0x3][usize::from(buf[0xc])]
+            ^ t[0x4][usize::from(buf[0xb])]
+            ^ t[0x5][usize::from(buf[0xa])]
+            ^ t[0x6][usize::from(buf[0x9])]
+            ^ t[0x7][usize::from(buf[0x8])]
+            ^ t[0x8][usize::from(buf[0x7])]
+            ^ t[0x9][usize::from(buf[0x6])]
+            ^ t[0xa][usize::from(buf[0x5])]
+            ^ t[0xb][usize::from(buf[0x4])]
+            ^ t[0xc][usize::from(buf[0x3] ^ crc as u8)]
+            ^ t[0xd][usize::from(buf[0x2] ^ (crc >> 8) as u8)]
+            ^ t[0xe][usize::from(buf[0x1] ^ (crc >> 16) as u8)]
+            ^ t[0xf][usize::from(buf[0x0] ^ (crc >> 24) as u8)];
+        buf = &buf[16..];
+    }
 
-        let chunk = chunks.remainder();
-        #[allow(clippy::needless_range_loop)]
-        for i in 0..chunk.len() {
-            bytes[i] = chunk[i].reverse_bits();
+    for &b in buf {
+        crc = (crc << 8) ^ t[0][usize::from(b ^ (crc >> 24) as u8)];
+    }
+
+    crc
+}
+
+const fn make_tables() -> [[u32; 256]; 16] {
+    let mut tables = [[0; 256]; 16];
+
+    let mut b = 0;
+    while b < 256 {
+        let mut crc = (b as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ POLYNOMIAL
+            } else {
+                crc << 1
+            };
+            bit += 1;
         }
 
-        self.val.update(&bytes[..chunk.len()]);
+        tables[0][b] = crc;
+        b += 1;
     }
 
-    pub fn finalize(&self) -> u32 {
-        let reversed = self.val.clone().finalize();
-        // CRC32B to CRC32
-        reversed.reverse_bits()
+    let mut n = 1;
+    while n < 16 {
+        let mut b = 0;
+        while b < 256 {
+            let prev = tables[n - 1][b];
+            tables[n][b] = (prev << 8) ^ tables[0][(prev >> 24) as usize];
+            b += 1;
+        }
+
+        n += 1;
     }
+
+    tables
 }
 
 #[cfg(test)]
@@ -49,4 +140,57 @@ mod tests {
         hasher.update(b"123456789");
         assert_eq!(hasher.finalize(), 0xFC891918);
     }
+
+    #[test]
+    fn table() {
+        assert_eq!(!update_table(!0, b"123456789"), 0xFC891918);
+
+        // split the data in two, so that the state is carried over
+        let buf = (0..5000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect::<Vec<_>>();
+        for len in (0..40).chain([511, 512, 513, 1999, 4999].iter().copied()) {
+            let (a, b) = buf[..len].split_at(len / 3);
+            let crc = !update_table(update_table(!0, a), b);
+
+            let mut hasher = Hasher::new();
+            hasher.update(&buf[..len]);
+            assert_eq!(crc, hasher.finalize(), "len {}", len);
+        }
+    }
+
+    #[cfg(feature = "nightly")]
+    mod bench {
+        extern crate test;
+
+        use self::test::Bencher;
+        use super::*;
+
+        fn setup() -> Vec<u8> {
+            (0..900 * 1024u32)
+                .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+                .collect()
+        }
+
+        #[bench]
+        fn update_table_900k(b: &mut Bencher) {
+            let buf = setup();
+            b.bytes = buf.len() as u64;
+
+            b.iter(|| update_table(!0, test::black_box(&buf)));
+        }
+
+        #[cfg(feature = "simd-crc")]
+        #[bench]
+        fn update_folded_900k(b: &mut Bencher) {
+            let buf = setup();
+            b.bytes = buf.len() as u64;
+
+            b.iter(|| {
+                let mut digest = crc_fast::Digest::new(crc_fast::CrcAlgorithm::Crc32Bzip2);
+                digest.update(test::black_box(&buf));
+                digest.finalize()
+            });
+        }
+    }
 }
@@ -1,5 +1,15 @@
 use std::mem;
 
+/// Links every entry of `tt` to the one following it in the decoded block
+///
+/// The lowest 8 bits of each entry hold its byte, the upper 24 bits the
+/// index of the next entry, so that each step of the walk touches a single
+/// entry. Returns the index of the first entry.
+///
+/// The walk is still one cache miss per byte. Walking the block from both
+/// ends, or packing two steps per entry, would need twice the memory per
+/// block and has measured slower than overlapping the misses with the
+/// run-length decoding in `Block::read_literals`.
 pub(crate) fn inverse_bwt(tt: &mut [u32], orig_ptr: usize, mut c: [u32; 256]) -> u32 {
     let mut sum = 0u32;
 
@@ -2,21 +2,152 @@ use std::error::Error as StdError;
 use std::fmt::{self, Display, Formatter};
 use std::io;
 
+use crate::decoder::DecoderError;
+
 /// An error returned by the block decoder
 ///
-/// At the moment it's not possible to find out what
-/// error occurred other than through the `Display`
-/// implementation, this will change in a future release.
+/// What went wrong can be found out through [`BlockError::kind`],
+/// while the `Display` implementation gives a more detailed description.
 #[derive(Debug, Clone, PartialEq)]
 pub struct BlockError {
+    kind: BlockErrorKind,
     reason: &'static str,
+    location: Option<Location>,
+}
+
+/// Where, in the compressed data, an error occurred
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Location {
+    /// The offset, in bits from the start of the compressed data,
+    /// of the magic of the block or of the end of stream marker
+    pub bit_offset: u64,
+    /// The index of the block within its stream, counting from `0`
+    ///
+    /// For errors occurring at the end of the stream this is the
+    /// number of blocks in the stream.
+    pub block_index: u32,
+}
+
+/// The kind of a [`BlockError`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BlockErrorKind {
+    /// The compressed data ended before `Field` could be read
+    Truncated(Field),
+    /// The CRC of the decoded data doesn't match the one stored in the block
+    BadCrc {
+        /// The CRC stored in the block
+        expected: u32,
+        /// The CRC of the decoded data
+        actual: u32,
+    },
+    /// The magic value isn't the one of a block nor the one of the end of the stream
+    BadMagic,
+    /// The symbol map doesn't declare any symbol
+    NoSymbols,
+    /// The number of huffman trees isn't `2..=6`
+    InvalidHuffmanTreeCount,
+    /// The number of selectors is too high
+    TooManySelectors,
+    /// A selector refers to a huffman tree which doesn't exist
+    SelectorOutOfRange,
+    /// There aren't enough selectors for the number of encoded symbols
+    NotEnoughSelectors,
+    /// The code lengths don't describe a valid huffman tree
+    InvalidHuffmanTree,
+    /// The decoded data is bigger than the blocksize declared in the header
+    BlockSizeExceeded,
+    /// The original pointer of the Burrows–Wheeler transform points outside of the block
+    OrigPtrOutOfBounds,
+    /// No block has been found in the data written to the decoder
+    NoBlocks,
+    /// More data has been written to the decoder after the end of the file
+    WriteAfterEof,
+    /// The block has been read before it was ready
+    NotReady,
+}
+
+/// A field of a block, or of the end of a stream
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Field {
+    /// The 48-bit magic value of a block or of the end of the stream
+    Magic,
+    /// The CRC of a block
+    Crc,
+    /// The combined CRC of a stream
+    StreamCrc,
+    /// The randomised bit
+    Randomised,
+    /// The original pointer of the Burrows–Wheeler transform
+    OrigPtr,
+    /// The map of the symbols used in the block
+    SymbolMap,
+    /// The number of huffman trees
+    HuffmanTreeCount,
+    /// The number of selectors
+    SelectorCount,
+    /// A selector
+    Selector,
+    /// The code lengths of a huffman tree
+    CodeLengths,
+    /// The huffman encoded symbols
+    Symbols,
 }
 
 impl BlockError {
     #[cold]
     #[inline(never)]
-    pub(crate) fn new(reason: &'static str) -> Self {
-        Self { reason }
+    pub(crate) fn new(kind: BlockErrorKind, reason: &'static str) -> Self {
+        Self {
+            kind,
+            reason,
+            location: None,
+        }
+    }
+
+    #[cold]
+    #[inline(never)]
+    pub(crate) fn truncated(field: Field) -> Self {
+        let reason = match field {
+            Field::Magic => "next magic truncated",
+            Field::Crc => "crc truncated",
+            Field::StreamCrc => "whole stream crc truncated",
+            Field::Randomised => "randomised truncated",
+            Field::OrigPtr => "orig ptr truncated",
+            Field::SymbolMap => "symbol range truncated",
+            Field::HuffmanTreeCount => "huffman groups truncated",
+            Field::SelectorCount => "selectors used truncated",
+            Field::Selector => "selector truncated",
+            Field::CodeLengths => "huffman code lengths truncated",
+            Field::Symbols => "huffman bitstream truncated",
+        };
+
+        Self::new(BlockErrorKind::Truncated(field), reason)
+    }
+
+    pub(crate) fn with_location(mut self, location: Location) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    /// The [`io::ErrorKind`] this error is converted into
+    pub(crate) fn io_error_kind(&self) -> io::ErrorKind {
+        match self.kind {
+            BlockErrorKind::Truncated(_) => io::ErrorKind::UnexpectedEof,
+            BlockErrorKind::NotReady | BlockErrorKind::WriteAfterEof => io::ErrorKind::Other,
+            _ => io::ErrorKind::InvalidData,
+        }
+    }
+
+    /// Returns the kind of this error
+    pub fn kind(&self) -> BlockErrorKind {
+        self.kind
+    }
+
+    /// Returns where this error occurred, if known
+    pub fn location(&self) -> Option<Location> {
+        self.location
     }
 }
 
@@ -30,7 +161,6 @@ impl StdError for BlockError {}
 
 impl From<BlockError> for io::Error {
     fn from(err: BlockError) -> io::Error {
-        // TODO: do better at this
-        io::Error::new(io::ErrorKind::Other, err)
+        DecoderError::Block(err).into()
     }
 }
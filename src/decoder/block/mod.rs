@@ -4,19 +4,44 @@ use std::mem;
 
 use tinyvec::ArrayVec;
 
-pub use self::error::BlockError;
+pub use self::error::{BlockError, BlockErrorKind, Field, Location};
+use self::randomise::Derandomiser;
 use crate::bitreader::BitReader;
 use crate::crc::Hasher;
+use crate::decoder::BufferPool;
 use crate::header::Header;
 use crate::huffman::HuffmanTree;
 use crate::move_to_front::MoveToFrontDecoder;
 
 mod bwt;
 mod error;
+mod randomise;
 
 pub(crate) const BLOCK_MAGIC: u64 = 0x314159265359;
 pub(crate) const FINAL_MAGIC: u64 = 0x177245385090;
 
+/// Returns the maximum size, in bytes, that a compressed block can take
+///
+/// Incompressible data can make a compressed block bigger than
+/// the maximum blocksize. In the worst case every symbol takes 20 bits,
+/// every selector 6 bits and every code length delta 39 bits.
+pub(crate) fn max_compressed_size(header: &Header) -> usize {
+    let symbols = header.max_blocksize() as usize + 1;
+    let selectors = (symbols + 49) / 50;
+    let tables = 6 * (5 + 258 * 39);
+    let fixed = 48 + 32 + 1 + 24 + 16 + 256 + 3 + 15;
+
+    (fixed + tables + selectors * 6 + symbols * 20 + 7) / 8
+}
+
+/// Returns the maximum size, in bytes, of the decompressed content of a block
+///
+/// The initial run-length encoding makes it bigger than the maximum blocksize:
+/// every 4 repeated bytes followed by a count decode to up to 259 bytes.
+pub(crate) fn max_uncompressed_size(header: &Header) -> u32 {
+    header.max_blocksize() / 5 * 259 + header.max_blocksize() % 5
+}
+
 pub(crate) struct Block {
     header: Header,
 
@@ -26,11 +51,17 @@ pub(crate) struct Block {
     last_byte: i16,
     byte_repeats: u8,
     repeats: u8,
+    // only present for randomised blocks
+    derandomiser: Option<Derandomiser>,
 
     hasher: Hasher,
     expected_crc: u32,
+    expected_stream_crc: u32,
+    verify_crc: bool,
 
     state: State,
+    // the pool `tt` is given back to on drop
+    pool: Option<BufferPool>,
 }
 
 enum State {
@@ -41,22 +72,40 @@ enum State {
 
 impl Block {
     pub fn new(header: Header) -> Self {
-        let max_blocksize = header.max_blocksize();
+        let max_blocksize = header.max_blocksize() as usize;
+
+        Self::with_tt(header, Vec::with_capacity(max_blocksize), None)
+    }
+
+    /// Construct a new [`Block`] whose `tt` buffer is taken from,
+    /// and later given back to, `pool`
+    pub fn with_pool(header: Header, pool: &BufferPool) -> Self {
+        let tt = pool.take_tt(header.max_blocksize() as usize);
+
+        Self::with_tt(header, tt, Some(pool.clone()))
+    }
+
+    fn with_tt(header: Header, tt: Vec<u32>, pool: Option<BufferPool>) -> Self {
+        debug_assert!(tt.is_empty());
 
         Self {
             header,
 
-            tt: Vec::with_capacity(max_blocksize as usize),
+            tt,
             pre_rle_used: 0,
             t_pos: 0,
             last_byte: -1,
             byte_repeats: 0,
             repeats: 0,
+            derandomiser: None,
 
             hasher: Hasher::new(),
             expected_crc: 0,
+            expected_stream_crc: 0,
+            verify_crc: true,
 
             state: State::NotReady,
+            pool,
         }
     }
 
@@ -67,13 +116,6 @@ impl Block {
         }
     }
 
-    pub fn is_reading(&self) -> bool {
-        match self.state {
-            State::Reading => true,
-            State::ReadyForRead | State::NotReady => false,
-        }
-    }
-
     pub fn is_not_ready(&self) -> bool {
         match self.state {
             State::NotReady => true,
@@ -85,6 +127,22 @@ impl Block {
         self.state = State::ReadyForRead;
     }
 
+    /// Set whether to compute the CRC of the decoded data and compare it
+    /// with the one stored in the block
+    pub fn set_verify_crc(&mut self, verify_crc: bool) {
+        self.verify_crc = verify_crc;
+    }
+
+    /// The CRC of the block, once it has been fully read and verified
+    pub fn crc(&self) -> u32 {
+        self.expected_crc
+    }
+
+    /// The combined CRC of the stream, once the end of stream marker has been read
+    pub fn stream_crc(&self) -> u32 {
+        self.expected_stream_crc
+    }
+
     pub fn read(
         &mut self,
         reader: &mut BitReader<'_>,
@@ -113,81 +171,127 @@ impl Block {
 
                 Ok(n)
             }
-            State::NotReady => Err(BlockError::new("not ready")),
+            State::NotReady => Err(BlockError::new(BlockErrorKind::NotReady, "not ready")),
         }
     }
 
     pub fn read_from_block(&mut self, out: &mut [u8]) -> Result<usize, BlockError> {
         let mut read = 0;
 
-        while (self.repeats > 0 || self.pre_rle_used < (self.tt.len() as u32)) && read < out.len() {
+        while read < out.len() {
             if self.repeats > 0 {
-                out[read] = self.last_byte as u8;
-                read += 1;
+                // the end of a run of more than 4 bytes
+                let n = usize::from(self.repeats).min(out.len() - read);
+                out[read..read + n].fill(self.last_byte as u8);
+                read += n;
 
-                self.repeats -= 1;
+                self.repeats -= n as u8;
                 if self.repeats == 0 {
                     self.last_byte = -1;
                 }
                 continue;
             }
 
-            self.t_pos = self.tt[self.t_pos as usize];
-            let b = self.t_pos as u8;
-            self.t_pos >>= 8;
-            self.pre_rle_used += 1;
-
-            if self.byte_repeats == 3 {
-                self.repeats = b;
-                self.byte_repeats = 0;
-                continue;
-            }
-
-            if self.last_byte == i16::from(b) {
-                self.byte_repeats += 1;
-            } else {
-                self.byte_repeats = 0;
+            if self.pre_rle_used == self.tt.len() as u32 {
+                break;
             }
-            self.last_byte = i16::from(b);
 
-            out[read] = b;
-            read += 1;
+            read += self.read_literals(&mut out[read..]);
         }
 
         if read == 0 && !out.is_empty() {
             self.state = State::NotReady;
 
+            if !self.verify_crc {
+                return Ok(0);
+            }
+
             let crc = self.hasher.finalize();
             return if self.expected_crc == crc {
                 Ok(0)
             } else {
-                Err(BlockError::new("bad crc"))
+                let kind = BlockErrorKind::BadCrc {
+                    expected: self.expected_crc,
+                    actual: crc,
+                };
+                Err(BlockError::new(kind, "bad crc"))
             };
         }
 
-        self.hasher.update(&out[..read]);
+        if self.verify_crc {
+            self.hasher.update(&out[..read]);
+        }
         Ok(read)
     }
 
+    /// Undo the burrows-wheeler transform, copying bytes into `out` until
+    /// it's full or the length of a run has to be read
+    ///
+    /// The state is kept in locals, so that this loop only waits for the
+    /// next entry of `tt`, which is usually a cache miss.
+    fn read_literals(&mut self, out: &mut [u8]) -> usize {
+        let tt = &self.tt[..];
+        let steps = out.len().min(tt.len() - self.pre_rle_used as usize);
+
+        let mut t_pos = self.t_pos;
+        let mut last_byte = self.last_byte;
+        let mut byte_repeats = self.byte_repeats;
+
+        let mut used = 0;
+        let mut written = 0;
+        while used < steps {
+            let entry = tt[t_pos as usize];
+            t_pos = entry >> 8;
+            let mut b = entry as u8;
+            if let Some(derandomiser) = &mut self.derandomiser {
+                b ^= derandomiser.next_mask();
+            }
+            used += 1;
+
+            if byte_repeats == 3 {
+                self.repeats = b;
+                byte_repeats = 0;
+                break;
+            }
+
+            if last_byte == i16::from(b) {
+                byte_repeats += 1;
+            } else {
+                byte_repeats = 0;
+            }
+            last_byte = i16::from(b);
+
+            out[written] = b;
+            written += 1;
+        }
+
+        self.t_pos = t_pos;
+        self.last_byte = last_byte;
+        self.byte_repeats = byte_repeats;
+        self.pre_rle_used += used as u32;
+        written
+    }
+
     pub fn read_block(&mut self, reader: &mut BitReader<'_>) -> Result<Option<()>, BlockError> {
         let magic = reader
             .read_u64(48)
-            .ok_or_else(|| BlockError::new("next magic truncated"))?;
+            .ok_or_else(|| BlockError::truncated(Field::Magic))?;
         match magic {
             BLOCK_MAGIC => {
                 self.do_read_block(reader)?;
                 Ok(Some(()))
             }
             FINAL_MAGIC => {
-                let _crc = reader
+                self.expected_stream_crc = reader
                     .read_u32(32)
-                    .ok_or_else(|| BlockError::new("whole stream crc truncated"))?;
-
-                // TODO: check whole stream crc
+                    .ok_or_else(|| BlockError::truncated(Field::StreamCrc))?;
 
                 Ok(None)
             }
-            _ => Err(BlockError::new("bad magic value found")),
+            _ => Err(BlockError::new(
+                BlockErrorKind::BadMagic,
+                "bad magic value found",
+            )),
         }
     }
 
@@ -197,38 +301,46 @@ impl Block {
 
         self.expected_crc = reader
             .read_u32(32)
-            .ok_or_else(|| BlockError::new("crc truncated"))?;
+            .ok_or_else(|| BlockError::truncated(Field::Crc))?;
 
         let randomised = reader
-            .next()
-            .ok_or_else(|| BlockError::new("randomised truncated"))?;
-        if randomised {
-            return Err(BlockError::new("randomised expected to be 'normal'"));
-        }
+            .read_bit()
+            .ok_or_else(|| BlockError::truncated(Field::Randomised))?;
+        self.derandomiser = if randomised {
+            Some(Derandomiser::new())
+        } else {
+            None
+        };
 
         let orig_ptr = reader
             .read_u32(24)
-            .ok_or_else(|| BlockError::new("orig ptr truncated"))?;
+            .ok_or_else(|| BlockError::truncated(Field::OrigPtr))?;
 
         let (mut huffman_decoder, alpha_size) = MoveToFrontDecoder::read_from_block(reader)?;
 
         let huffman_groups = reader
             .read_u8(3)
-            .ok_or_else(|| BlockError::new("huffmann groups truncated"))?;
+            .ok_or_else(|| BlockError::truncated(Field::HuffmanTreeCount))?;
         if !(2..=6).contains(&huffman_groups) {
-            return Err(BlockError::new("invalid number of huffman trees"));
+            return Err(BlockError::new(
+                BlockErrorKind::InvalidHuffmanTreeCount,
+                "invalid number of huffman trees",
+            ));
         }
 
         let num_selectors = reader
             .read_u16(15)
-            .ok_or_else(|| BlockError::new("selectors used truncated"))?;
+            .ok_or_else(|| BlockError::truncated(Field::SelectorCount))?;
 
         #[cfg(feature = "nightly")]
         let mut reverse_selectors = ArrayVec::<[u8; 18001]>::new();
         #[cfg(feature = "nightly")]
         {
             if num_selectors > 18001 {
-                return Err(BlockError::new("too high value for num_selectors"));
+                return Err(BlockError::new(
+                    BlockErrorKind::TooManySelectors,
+                    "too high value for num_selectors",
+                ));
             }
             reverse_selectors.set_len(usize::from(num_selectors));
         }
@@ -237,19 +349,19 @@ impl Block {
 
         let mut selectors_decoder = MoveToFrontDecoder::new();
         for selector in reverse_selectors.iter_mut().rev() {
-            let mut trees = 0;
-
-            while reader
-                .next()
-                .ok_or_else(|| BlockError::new("selector truncated"))?
-            {
-                trees += 1;
-
-                if trees >= huffman_groups {
-                    return Err(BlockError::new("tree index too large"));
-                }
+            // the tree index is unary encoded, and there are at most 6 trees
+            let trees = (reader.peek_bits(6) << (32 - 6)).leading_ones() as u8;
+            if trees >= huffman_groups {
+                return Err(BlockError::new(
+                    BlockErrorKind::SelectorOutOfRange,
+                    "tree index too large",
+                ));
             }
 
+            reader
+                .consume(trees + 1)
+                .ok_or_else(|| BlockError::truncated(Field::Selector))?;
+
             *selector = selectors_decoder.decode_small(trees);
         }
 
@@ -261,25 +373,30 @@ impl Block {
         for _ in 0..huffman_groups {
             let mut length = reader
                 .read_u8(5)
-                .ok_or_else(|| BlockError::new("huffman group length truncated"))?;
+                .ok_or_else(|| BlockError::truncated(Field::CodeLengths))?;
 
             for length_item in &mut *lengths {
                 loop {
                     if !(1..=20).contains(&length) {
-                        return Err(BlockError::new("huffman length out of range"));
+                        return Err(BlockError::new(
+                            BlockErrorKind::InvalidHuffmanTree,
+                            "huffman length out of range",
+                        ));
                     }
 
-                    if !reader
-                        .next()
-                        .ok_or_else(|| BlockError::new("length bit1 truncated"))?
-                    {
+                    // `0` ends the code length, `10` increments it and `11` decrements it
+                    let bits = reader.peek_bits(2);
+                    if bits & 0b10 == 0 {
+                        reader
+                            .consume(1)
+                            .ok_or_else(|| BlockError::truncated(Field::CodeLengths))?;
                         break;
                     }
 
-                    if reader
-                        .next()
-                        .ok_or_else(|| BlockError::new("length bit2 truncated"))?
-                    {
+                    reader
+                        .consume(2)
+                        .ok_or_else(|| BlockError::truncated(Field::CodeLengths))?;
+                    if bits & 0b01 != 0 {
                         length -= 1;
                     } else {
                         length += 1;
@@ -289,16 +406,24 @@ impl Block {
                 *length_item = length;
             }
 
-            let tree = HuffmanTree::new(&lengths).map_err(BlockError::new)?;
+            let tree = HuffmanTree::new(&lengths)
+                .map_err(|reason| BlockError::new(BlockErrorKind::InvalidHuffmanTree, reason))?;
             huffman_trees.push(tree);
         }
 
-        let selector = reverse_selectors
-            .pop()
-            .ok_or_else(|| BlockError::new("no tree selectors given"))?;
-        let mut current_huffman_tree = huffman_trees
-            .get(usize::from(selector))
-            .ok_or_else(|| BlockError::new("tree selector out of range"))?;
+        let selector = reverse_selectors.pop().ok_or_else(|| {
+            BlockError::new(
+                BlockErrorKind::NotEnoughSelectors,
+                "no tree selectors given",
+            )
+        })?;
+        let mut current_huffman_tree =
+            huffman_trees.get(usize::from(selector)).ok_or_else(|| {
+                BlockError::new(
+                    BlockErrorKind::SelectorOutOfRange,
+                    "tree selector out of range",
+                )
+            })?;
 
         let mut repeat = 0u32;
         let mut repeat_power = 0u32;
@@ -308,18 +433,25 @@ impl Block {
         loop {
             if decoded == 50 {
                 let selector = reverse_selectors.pop().ok_or_else(|| {
-                    BlockError::new("insufficient selector indices for number of symbols")
+                    BlockError::new(
+                        BlockErrorKind::NotEnoughSelectors,
+                        "insufficient selector indices for number of symbols",
+                    )
                 })?;
 
-                current_huffman_tree = huffman_trees
-                    .get(usize::from(selector))
-                    .ok_or_else(|| BlockError::new("tree selector out of range"))?;
+                current_huffman_tree =
+                    huffman_trees.get(usize::from(selector)).ok_or_else(|| {
+                        BlockError::new(
+                            BlockErrorKind::SelectorOutOfRange,
+                            "tree selector out of range",
+                        )
+                    })?;
                 decoded = 0;
             }
 
             let v = current_huffman_tree
                 .decode(reader)
-                .ok_or_else(|| BlockError::new("huffman bitstream truncated"))?;
+                .ok_or_else(|| BlockError::truncated(Field::Symbols))?;
             decoded += 1;
 
             if v < 2 {
@@ -330,7 +462,10 @@ impl Block {
                 repeat_power <<= 1;
 
                 if repeat > 2 * 1024 * 1024 {
-                    return Err(BlockError::new("repeat count too large"));
+                    return Err(BlockError::new(
+                        BlockErrorKind::BlockSizeExceeded,
+                        "repeat count too large",
+                    ));
                 }
                 continue;
             }
@@ -338,7 +473,10 @@ impl Block {
             let old_repeat = mem::replace(&mut repeat, 0);
             if old_repeat > 0 {
                 if old_repeat > self.header.max_blocksize() - (self.tt.len() as u32) {
-                    return Err(BlockError::new("repeats past end of block"));
+                    return Err(BlockError::new(
+                        BlockErrorKind::BlockSizeExceeded,
+                        "repeats past end of block",
+                    ));
                 }
 
                 let b = huffman_decoder.first();
@@ -354,7 +492,10 @@ impl Block {
 
             let b = huffman_decoder.decode((v - 1) as u8);
             if self.tt.len() >= self.header.max_blocksize() as usize {
-                return Err(BlockError::new("data exceeds block size"));
+                return Err(BlockError::new(
+                    BlockErrorKind::BlockSizeExceeded,
+                    "data exceeds block size",
+                ));
             }
 
             self.tt.push(u32::from(b));
@@ -362,7 +503,10 @@ impl Block {
         }
 
         if (orig_ptr as usize) >= self.tt.len() {
-            return Err(BlockError::new("orig_ptr out of bounds"));
+            return Err(BlockError::new(
+                BlockErrorKind::OrigPtrOutOfBounds,
+                "orig_ptr out of bounds",
+            ));
         }
 
         self.pre_rle_used = 0;
@@ -375,6 +519,14 @@ impl Block {
     }
 }
 
+impl Drop for Block {
+    fn drop(&mut self) {
+        if let Some(pool) = &self.pool {
+            pool.put_tt(mem::replace(&mut self.tt, Vec::new()));
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::convert::TryInto;
@@ -442,4 +594,85 @@ mod tests {
         let read = reader.read(&mut bits, &mut out).unwrap();
         assert_eq!(&out[..read], decompressed.as_ref());
     }
+
+    #[test]
+    fn bad_crc() {
+        let mut compressed = include_bytes!("../../../tests/samplefiles/sample3.bz2").to_vec();
+        let expected = u32::from_be_bytes(compressed[10..14].try_into().unwrap());
+        compressed[10] ^= 0xff;
+
+        let header = Header::parse(compressed[..4].try_into().unwrap()).unwrap();
+        let mut bits = BitReader::new([&compressed[4..], &[]]);
+        let mut reader = Block::new(header);
+
+        let mut out = vec![0u8; 1024 * 1024];
+
+        reader.set_ready_for_read();
+        let read = reader.read(&mut bits, &mut out).unwrap();
+        assert!(read > 0);
+        let err = reader.read(&mut bits, &mut out).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            BlockErrorKind::BadCrc {
+                expected: expected ^ 0xff00_0000,
+                actual: expected,
+            }
+        );
+    }
+
+    #[test]
+    fn no_verify_crc() {
+        let mut compressed = include_bytes!("../../../tests/samplefiles/sample3.bz2").to_vec();
+        let decompressed = include_bytes!("../../../tests/samplefiles/sample3.ref");
+        compressed[10] ^= 0xff;
+
+        let header = Header::parse(compressed[..4].try_into().unwrap()).unwrap();
+        let mut bits = BitReader::new([&compressed[4..], &[]]);
+        let mut reader = Block::new(header);
+        reader.set_verify_crc(false);
+
+        let mut out = vec![0u8; decompressed.len()];
+
+        reader.set_ready_for_read();
+        let read = reader.read(&mut bits, &mut out).unwrap();
+        assert_eq!(&out[..read], decompressed.as_ref());
+        assert_eq!(reader.read(&mut bits, &mut out).unwrap(), 0);
+
+        // the decoded bytes never went through the hasher
+        assert_eq!(reader.hasher.finalize(), Hasher::new().finalize());
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut compressed = include_bytes!("../../../tests/samplefiles/sample3.bz2").to_vec();
+        compressed[4] ^= 0xff;
+
+        let header = Header::parse(compressed[..4].try_into().unwrap()).unwrap();
+        let mut bits = BitReader::new([&compressed[4..], &[]]);
+        let mut reader = Block::new(header);
+
+        reader.set_ready_for_read();
+        let err = reader.read(&mut bits, &mut [0; 1024]).unwrap_err();
+        assert_eq!(err.kind(), BlockErrorKind::BadMagic);
+    }
+
+    #[test]
+    fn truncated() {
+        let compressed = include_bytes!("../../../tests/samplefiles/sample1.bz2");
+
+        let header = Header::parse(compressed[..4].try_into().unwrap()).unwrap();
+        let mut bits = BitReader::new([&compressed[4..compressed.len() / 2], &[]]);
+        let mut reader = Block::new(header.clone());
+
+        reader.set_ready_for_read();
+        let err = reader.read(&mut bits, &mut [0; 1024]).unwrap_err();
+        assert_eq!(err.kind(), BlockErrorKind::Truncated(Field::Symbols));
+
+        let mut bits = BitReader::new([&compressed[4..12], &[]]);
+        let mut reader = Block::new(header);
+
+        reader.set_ready_for_read();
+        let err = reader.read(&mut bits, &mut [0; 1024]).unwrap_err();
+        assert_eq!(err.kind(), BlockErrorKind::Truncated(Field::Crc));
+    }
 }
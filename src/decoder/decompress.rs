@@ -0,0 +1,195 @@
+use std::convert::TryInto;
+
+use super::block::{Block, Location};
+use super::{BufferPool, DecoderError, DecoderOptions, ParallelDecoder, ReadState};
+use crate::bitreader::BitReader;
+use crate::crc;
+use crate::header::{Header, HeaderError};
+use crate::ThreadPool;
+
+/// How much the output `Vec` is grown by, before reading into it
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// Decompress the bzip2 file `compressed` in one go
+///
+/// Files made of multiple concatenated streams are decoded in their entirety,
+/// like [`Decoder`] does by default. Unlike [`Decoder`], the blocks are decoded
+/// directly from `compressed`, without copying it into an internal buffer first.
+///
+/// ```rust
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let compressed_file: &[u8] = include_bytes!("../../tests/samplefiles/sample1.bz2").as_ref();
+///
+/// let decompressed_file = bzip2_rs::decompress(compressed_file)?;
+/// #
+/// # let expected: &[u8] = include_bytes!("../../tests/samplefiles/sample1.ref").as_ref();
+/// # assert_eq!(decompressed_file, expected);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Decoder`]: crate::decoder::Decoder
+pub fn decompress(compressed: &[u8]) -> Result<Vec<u8>, DecoderError> {
+    let mut out = Vec::new();
+
+    decode_blocks(compressed, |block, location| loop {
+        let filled = out.len();
+        out.resize(filled + CHUNK_LEN, 0);
+
+        let read = block
+            .read_from_block(&mut out[filled..])
+            .map_err(|err| err.with_location(location))?;
+        out.truncate(filled + read);
+        if read == 0 {
+            return Ok(());
+        }
+    })?;
+
+    Ok(out)
+}
+
+/// Decompress the bzip2 file `compressed` into `out`, returning the size
+/// of the decompressed data
+///
+/// Returns [`DecoderError::BufferTooSmall`] if the decompressed data
+/// doesn't fit into `out`. See [`decompress`] for more details.
+///
+/// ```rust
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let compressed_file: &[u8] = include_bytes!("../../tests/samplefiles/sample1.bz2").as_ref();
+///
+/// let mut buf = vec![0; 128 * 1024];
+/// let n = bzip2_rs::decompress_into(compressed_file, &mut buf)?;
+/// let decompressed_file = &buf[..n];
+/// #
+/// # let expected: &[u8] = include_bytes!("../../tests/samplefiles/sample1.ref").as_ref();
+/// # assert_eq!(decompressed_file, expected);
+/// # Ok(())
+/// # }
+/// ```
+pub fn decompress_into(compressed: &[u8], out: &mut [u8]) -> Result<usize, DecoderError> {
+    let mut written = 0;
+
+    decode_blocks(compressed, |block, location| loop {
+        let read = if written < out.len() {
+            block.read_from_block(&mut out[written..])
+        } else {
+            // reaching the end of the block is the only way of knowing whether
+            // there's more data, and it's also when the crc gets verified
+            block.read_from_block(&mut [0])
+        }
+        .map_err(|err| err.with_location(location))?;
+
+        if read == 0 {
+            return Ok(());
+        }
+        if written == out.len() {
+            return Err(DecoderError::BufferTooSmall);
+        }
+
+        written += read;
+    })?;
+
+    Ok(written)
+}
+
+/// Decompress the bzip2 file `compressed` in one go, decoding its
+/// blocks in parallel on `pool`
+///
+/// This behaves like [`decompress`], except for using a [`ParallelDecoder`],
+/// which has to copy `compressed` so that it can be shared with the threads of `pool`.
+///
+/// [`ParallelDecoder`]: crate::decoder::ParallelDecoder
+pub fn decompress_parallel<P: ThreadPool>(
+    compressed: &[u8],
+    pool: P,
+) -> Result<Vec<u8>, DecoderError> {
+    // every block is decoded entirely by the threads of `pool`
+    let options = DecoderOptions::new().max_preread_len(usize::MAX);
+    let mut decoder = ParallelDecoder::with_options(pool, options);
+    decoder.write(compressed)?;
+    decoder.write(&[])?;
+
+    let mut out = Vec::new();
+    loop {
+        let filled = out.len();
+        out.resize(filled + CHUNK_LEN, 0);
+
+        match decoder.read(&mut out[filled..])? {
+            ReadState::Read(read) => out.truncate(filled + read),
+            ReadState::Eof => {
+                out.truncate(filled);
+                return Ok(out);
+            }
+            // everything has been written already, so the first stream header is missing
+            ReadState::NeedsWrite => return Err(HeaderError::InvalidSignature.into()),
+        }
+    }
+}
+
+/// Decode the blocks of every stream in `compressed`, calling `read_block`
+/// for reading each of them to the end
+fn decode_blocks<F>(compressed: &[u8], mut read_block: F) -> Result<(), DecoderError>
+where
+    F: FnMut(&mut Block, Location) -> Result<(), DecoderError>,
+{
+    // reuses the buffer of the block across streams
+    let buffer_pool = BufferPool::new();
+
+    let mut stream_offset = 0;
+    let mut streams = 0;
+    loop {
+        let header = match compressed.get(stream_offset..stream_offset + 4) {
+            Some(raw_header) => Header::parse(raw_header.try_into().unwrap()),
+            None => Err(HeaderError::InvalidSignature),
+        };
+        let header = match header {
+            Ok(header) => header,
+            // no more streams, or trailing garbage after the last one
+            Err(_) if streams > 0 => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut block = Block::with_pool(header, &buffer_pool);
+        let mut bit_offset = (stream_offset as u64 + 4) * 8;
+        let mut stream_crc = 0;
+        let mut block_index = 0;
+        loop {
+            let location = Location {
+                bit_offset,
+                block_index,
+            };
+
+            // a new reader for every block, so that its position can't overflow
+            let mut reader = BitReader::new([&compressed[(bit_offset / 8) as usize..], &[]]);
+            reader.skip((bit_offset % 8) as u32).expect("enough bits");
+
+            let found_block = block
+                .read_block(&mut reader)
+                .map_err(|err| err.with_location(location))?;
+            bit_offset += u64::from(reader.position()) - bit_offset % 8;
+
+            if found_block.is_none() {
+                // the end of stream marker has been read
+                let expected = block.stream_crc();
+                if expected != stream_crc {
+                    return Err(DecoderError::BadStreamCrc {
+                        expected,
+                        actual: stream_crc,
+                        location,
+                    });
+                }
+
+                break;
+            }
+
+            read_block(&mut block, location)?;
+            stream_crc = crc::combine(stream_crc, block.crc());
+            block_index += 1;
+        }
+
+        // the next stream starts at the next byte boundary
+        streams += 1;
+        stream_offset = ((bit_offset + 7) / 8) as usize;
+    }
+}
@@ -2,7 +2,8 @@ use std::error::Error as StdError;
 use std::fmt::{self, Display, Formatter};
 use std::io;
 
-use super::block::BlockError;
+use super::block::{BlockError, Location};
+use super::Limit;
 use crate::header::HeaderError;
 
 /// An error returned by [`Decoder`] or [`DecoderReader`]
@@ -10,11 +11,45 @@ use crate::header::HeaderError;
 /// [`Decoder`]: crate::decoder::Decoder
 /// [`DecoderReader`]: crate::decoder::DecoderReader
 #[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
 pub enum DecoderError {
     /// An error was returned by the `Header` decoder
     Header(HeaderError),
     /// An error was returned by the `Block` decoder
     Block(BlockError),
+    /// The combined CRC of the blocks doesn't match the one stored at the end of the stream
+    BadStreamCrc {
+        /// The CRC stored at the end of the stream
+        expected: u32,
+        /// The CRC combined from the CRCs of the decoded blocks
+        actual: u32,
+        /// The location of the end of stream marker
+        location: Location,
+    },
+    /// A limit set through [`Limits`] has been exceeded
+    ///
+    /// [`Limits`]: crate::decoder::Limits
+    LimitExceeded(Limit),
+    /// The decompressed data doesn't fit into the buffer passed to [`decompress_into`]
+    ///
+    /// [`decompress_into`]: crate::decompress_into
+    BufferTooSmall,
+}
+
+impl DecoderError {
+    /// Returns where this error occurred, if known
+    ///
+    /// Errors in the stream header, exceeded limits and buffers
+    /// which are too small don't have a location.
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            DecoderError::Header(_) => None,
+            DecoderError::Block(err) => err.location(),
+            DecoderError::BadStreamCrc { location, .. } => Some(*location),
+            DecoderError::LimitExceeded(_) => None,
+            DecoderError::BufferTooSmall => None,
+        }
+    }
 }
 
 impl Display for DecoderError {
@@ -22,6 +57,23 @@ impl Display for DecoderError {
         match self {
             DecoderError::Header(err) => write!(f, "header: {}", err),
             DecoderError::Block(err) => write!(f, "block: {}", err),
+            DecoderError::BadStreamCrc {
+                expected, actual, ..
+            } => write!(
+                f,
+                "bad stream crc: expected {:#010x}, found {:#010x}",
+                expected, actual
+            ),
+            DecoderError::LimitExceeded(limit) => {
+                let limit = match limit {
+                    Limit::UncompressedLen => "uncompressed length",
+                    Limit::Blocks => "number of blocks",
+                    Limit::Streams => "number of streams",
+                    Limit::BufferedMemory => "buffered memory",
+                };
+                write!(f, "limit exceeded: {}", limit)
+            }
+            DecoderError::BufferTooSmall => f.write_str("output buffer too small"),
         }
     }
 }
@@ -41,10 +93,21 @@ impl From<BlockError> for DecoderError {
 impl StdError for DecoderError {}
 
 impl From<DecoderError> for io::Error {
+    /// Convert `err` into an [`io::Error`]
+    ///
+    /// Truncated data is reported as [`io::ErrorKind::UnexpectedEof`], while
+    /// corrupted data is reported as [`io::ErrorKind::InvalidData`].
+    /// Exceeded limits and buffers which are too small are reported as [`io::ErrorKind::Other`].
+    /// The original error can be retrieved by downcasting the inner error
+    /// of the [`io::Error`] into a [`DecoderError`].
     fn from(err: DecoderError) -> io::Error {
-        match err {
-            DecoderError::Header(err) => err.into(),
-            DecoderError::Block(err) => err.into(),
-        }
+        let kind = match &err {
+            DecoderError::Header(_) => io::ErrorKind::InvalidData,
+            DecoderError::Block(err) => err.io_error_kind(),
+            DecoderError::BadStreamCrc { .. } => io::ErrorKind::InvalidData,
+            DecoderError::LimitExceeded(_) | DecoderError::BufferTooSmall => io::ErrorKind::Other,
+        };
+
+        io::Error::new(kind, err)
     }
 }
@@ -0,0 +1,318 @@
+use std::convert::TryInto;
+use std::mem;
+
+use super::{BlockIndex, IndexedBlock};
+use crate::bitreader::BitReader;
+use crate::crc;
+use crate::decoder::block::{
+    max_compressed_size, Block, BlockError, BlockErrorKind, Field, Location, BLOCK_MAGIC,
+    FINAL_MAGIC,
+};
+use crate::decoder::parallel::scanner::iter::SignatureFinder;
+use crate::decoder::{BufferPool, DecoderError};
+use crate::header::Header;
+
+/// A low-level builder of [`BlockIndex`]es
+///
+/// This builder does no IO by itself, instead the compressed file
+/// has to be written to it, in order, after which [`BlockIndexBuilder::finish`]
+/// returns the [`BlockIndex`]. Blocks are decoded as soon as all of their
+/// compressed data has been written, so only a few blocks are kept in memory
+/// at the same time.
+///
+/// Files made of multiple concatenated streams are supported, while
+/// trailing data after the last stream is ignored.
+pub struct BlockIndexBuilder {
+    // the header of the current stream and the block reused for decoding
+    // all of its blocks, `None` when the next stream header is expected
+    header_block: Option<(Header, Block)>,
+    max_header: Option<Header>,
+
+    in_buf: Vec<u8>,
+    // the offset, in bits, of the start of `in_buf` in the compressed data
+    in_buf_offset: u64,
+    // the position, in bits into `in_buf`, of the next magic or stream header
+    pos: u64,
+    // the position, in bytes into `in_buf`, from which to continue
+    // looking for the magic following the one at `pos`
+    scan_pos: usize,
+
+    blocks: Vec<IndexedBlock>,
+    uncompressed_len: u64,
+
+    stream_block_index: u32,
+    stream_crc: u32,
+    streams: u32,
+
+    // space for decoding blocks into, since only their size is needed
+    scratch: Vec<u8>,
+    // reuses the buffer of the block across streams
+    buffer_pool: BufferPool,
+    // trailing garbage has been found after the last stream
+    done: bool,
+}
+
+impl BlockIndexBuilder {
+    /// Construct a new [`BlockIndexBuilder`], ready to index a new bzip2 file
+    pub fn new() -> Self {
+        Self {
+            header_block: None,
+            max_header: None,
+
+            in_buf: Vec::new(),
+            in_buf_offset: 0,
+            pos: 0,
+            scan_pos: 0,
+
+            blocks: Vec::new(),
+            uncompressed_len: 0,
+
+            stream_block_index: 0,
+            stream_crc: 0,
+            streams: 0,
+
+            scratch: vec![0; 32 * 1024],
+            buffer_pool: BufferPool::new(),
+            done: false,
+        }
+    }
+
+    /// Write more compressed data into this [`BlockIndexBuilder`],
+    /// indexing every block that has been fully written
+    pub fn write(&mut self, buf: &[u8]) -> Result<(), DecoderError> {
+        if self.done {
+            return Ok(());
+        }
+
+        self.in_buf.extend_from_slice(buf);
+        self.index_blocks(false)?;
+
+        // forget about the data which has already been indexed
+        let consumed = (self.pos / 8) as usize;
+        self.in_buf.drain(..consumed);
+        self.in_buf_offset += consumed as u64 * 8;
+        self.pos -= consumed as u64 * 8;
+        self.scan_pos -= consumed.min(self.scan_pos);
+
+        Ok(())
+    }
+
+    /// Index the remaining blocks and return the [`BlockIndex`]
+    pub fn finish(mut self) -> Result<BlockIndex, DecoderError> {
+        if !self.done {
+            self.index_blocks(true)?;
+        }
+
+        match self.max_header {
+            Some(header) if self.streams > 0 => Ok(BlockIndex {
+                header,
+                blocks: self.blocks,
+                uncompressed_len: self.uncompressed_len,
+            }),
+            _ => Err(BlockError::truncated(Field::Magic)
+                .with_location(self.location())
+                .into()),
+        }
+    }
+
+    fn index_blocks(&mut self, eof: bool) -> Result<(), DecoderError> {
+        loop {
+            let max_size = match &self.header_block {
+                Some((header, _)) => max_compressed_size(header),
+                None => {
+                    if !self.read_stream_header(eof)? {
+                        return Ok(());
+                    }
+                    continue;
+                }
+            };
+
+            let skip_bytes = (self.pos / 8) as usize;
+            let skip_bits = self.pos % 8;
+
+            let mut reader = BitReader::new([&self.in_buf[skip_bytes..], &[]]);
+            reader.skip(skip_bits as u32).expect("enough bits");
+
+            match reader.read_u64(48) {
+                Some(BLOCK_MAGIC) => {}
+                Some(FINAL_MAGIC) => {
+                    let expected = match reader.read_u32(32) {
+                        Some(expected) => expected,
+                        None if eof => {
+                            return Err(BlockError::truncated(Field::StreamCrc)
+                                .with_location(self.location())
+                                .into())
+                        }
+                        None => return Ok(()),
+                    };
+
+                    let actual = mem::replace(&mut self.stream_crc, 0);
+                    if expected != actual {
+                        return Err(DecoderError::BadStreamCrc {
+                            expected,
+                            actual,
+                            location: self.location(),
+                        });
+                    }
+
+                    // the next stream starts at the next byte boundary
+                    self.pos = (self.pos + 48 + 32 + 7) / 8 * 8;
+                    self.scan_pos = 0;
+                    self.header_block = None;
+                    self.stream_block_index = 0;
+                    self.streams += 1;
+                    continue;
+                }
+                Some(_) => {
+                    return Err(
+                        BlockError::new(BlockErrorKind::BadMagic, "bad magic value found")
+                            .with_location(self.location())
+                            .into(),
+                    )
+                }
+                None if eof => {
+                    return Err(BlockError::truncated(Field::Magic)
+                        .with_location(self.location())
+                        .into())
+                }
+                None => return Ok(()),
+            }
+
+            // the block is certainly complete if the next signature
+            // is in the buffer or enough data has been buffered
+            let available = self.in_buf.len() - skip_bytes;
+            if !eof && available < max_size && !self.find_next_signature() {
+                return Ok(());
+            }
+
+            match self.index_block(skip_bytes, skip_bits) {
+                Ok(()) => {}
+                Err(err) if !eof && matches!(err.kind(), BlockErrorKind::Truncated(_)) => {
+                    // the next signature was inside of the compressed data
+                    // of this block, which hasn't been fully written yet
+                    return Ok(());
+                }
+                Err(err) => return Err(err.with_location(self.location()).into()),
+            }
+        }
+    }
+
+    /// Look for the magic following the one at `self.pos`, only scanning
+    /// the data which hasn't already been scanned by a previous call
+    fn find_next_signature(&mut self) -> bool {
+        let after_magic = self.pos + 48;
+        let start = self.scan_pos.max((after_magic / 8) as usize);
+
+        // bit signatures inside of the compressed data of a block can be
+        // found by chance, skip them by only looking after the current magic
+        let signature = SignatureFinder::new(&self.in_buf[start..])
+            .map(|signature| start as u64 * 8 + signature)
+            .find(|&signature| signature >= after_magic);
+
+        match signature {
+            Some(signature) => {
+                // if it was found by chance, look for the next one once more data is written
+                self.scan_pos = (signature / 8) as usize + 1;
+                true
+            }
+            None => {
+                // the last 47 bits could be the start of a signature
+                self.scan_pos = self.in_buf.len().saturating_sub(6).max(start);
+                false
+            }
+        }
+    }
+
+    /// Parse the header of the next stream
+    ///
+    /// Returns `false` if more data is needed or no more streams are available.
+    fn read_stream_header(&mut self, eof: bool) -> Result<bool, DecoderError> {
+        debug_assert_eq!(self.pos % 8, 0);
+        let skip_bytes = (self.pos / 8) as usize;
+
+        let raw_header = match self.in_buf.get(skip_bytes..skip_bytes + 4) {
+            Some(raw_header) => raw_header,
+            None if eof && self.streams > 0 => {
+                // no more streams
+                self.done = true;
+                return Ok(false);
+            }
+            None => return Ok(false),
+        };
+
+        match Header::parse(raw_header.try_into().unwrap()) {
+            Ok(header) => {
+                let is_bigger = match &self.max_header {
+                    Some(max_header) => header.max_blocksize() > max_header.max_blocksize(),
+                    None => true,
+                };
+                if is_bigger {
+                    self.max_header = Some(header.clone());
+                }
+
+                let block = Block::with_pool(header.clone(), &self.buffer_pool);
+                self.header_block = Some((header, block));
+                self.pos += 4 * 8;
+                Ok(true)
+            }
+            Err(_) if self.streams > 0 => {
+                // trailing garbage after the last stream
+                self.done = true;
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Decode the block at `self.pos`, in order to find its size and where it ends
+    fn index_block(&mut self, skip_bytes: usize, skip_bits: u64) -> Result<(), BlockError> {
+        let (_, block) = self
+            .header_block
+            .as_mut()
+            .expect("the stream header has been read");
+
+        let mut reader = BitReader::new([&self.in_buf[skip_bytes..], &[]]);
+        reader.skip(skip_bits as u32).expect("enough bits");
+
+        let is_block = block.read_block(&mut reader)?;
+        debug_assert!(is_block.is_some(), "only blocks are indexed");
+        let end = u64::from(reader.position());
+
+        let mut uncompressed_len = 0u32;
+        loop {
+            let read = block.read_from_block(&mut self.scratch)?;
+            if read == 0 {
+                break;
+            }
+
+            uncompressed_len += read as u32;
+        }
+
+        self.blocks.push(IndexedBlock {
+            compressed_bit_offset: self.in_buf_offset + self.pos,
+            uncompressed_offset: self.uncompressed_len,
+            uncompressed_len,
+            crc: block.crc(),
+        });
+        self.uncompressed_len += u64::from(uncompressed_len);
+        self.stream_crc = crc::combine(self.stream_crc, block.crc());
+        self.stream_block_index += 1;
+
+        self.pos = skip_bytes as u64 * 8 + end;
+        self.scan_pos = 0;
+        Ok(())
+    }
+
+    fn location(&self) -> Location {
+        Location {
+            bit_offset: self.in_buf_offset + self.pos,
+            block_index: self.stream_block_index,
+        }
+    }
+}
+
+impl Default for BlockIndexBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,261 @@
+//! Block index, for random access into bzip2 files
+
+use std::io::{self, Read};
+
+pub use self::builder::BlockIndexBuilder;
+use crate::header::Header;
+
+mod builder;
+mod serialize;
+
+/// An index of the blocks of a bzip2 file
+///
+/// Every block of a bzip2 file can be decoded independently of the others,
+/// as long as the position where its compressed data starts is known.
+/// [`BlockIndex`] records, for every block, where its compressed data starts
+/// and which range of the decompressed file it decodes to, so that
+/// [`SeekableDecoderReader`] can decompress any range of the file by only
+/// decoding the blocks it overlaps with.
+///
+/// ```rust
+/// use bzip2_rs::decoder::BlockIndex;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let compressed_file: &[u8] = include_bytes!("../../../tests/samplefiles/sample2.bz2").as_ref();
+///
+/// let index = BlockIndex::build(compressed_file)?;
+/// assert_eq!(index.blocks().len(), 2);
+/// assert_eq!(index.uncompressed_len(), 212340);
+/// #
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`SeekableDecoderReader`]: crate::decoder::SeekableDecoderReader
+#[derive(Clone)]
+pub struct BlockIndex {
+    header: Header,
+    blocks: Vec<IndexedBlock>,
+    uncompressed_len: u64,
+}
+
+/// A block recorded in a [`BlockIndex`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IndexedBlock {
+    compressed_bit_offset: u64,
+    uncompressed_offset: u64,
+    uncompressed_len: u32,
+    crc: u32,
+}
+
+impl BlockIndex {
+    /// Build the [`BlockIndex`] of the bzip2 file read from `reader`
+    ///
+    /// This requires decoding the whole file. See [`BlockIndexBuilder`]
+    /// for building it incrementally.
+    pub fn build<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut builder = BlockIndexBuilder::new();
+
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let read = reader.read(&mut buf)?;
+            if read == 0 {
+                return builder.finish().map_err(io::Error::from);
+            }
+
+            builder.write(&buf[..read])?;
+        }
+    }
+
+    /// The header with the largest blocksize among the ones of the streams of the file
+    ///
+    /// It's used for decoding any of the blocks in the index.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// The blocks of the file, in order
+    pub fn blocks(&self) -> &[IndexedBlock] {
+        &self.blocks
+    }
+
+    /// The size of the decompressed file
+    pub fn uncompressed_len(&self) -> u64 {
+        self.uncompressed_len
+    }
+
+    /// Returns the index of the block containing the decompressed byte at `uncompressed_offset`
+    pub(crate) fn find(&self, uncompressed_offset: u64) -> Option<usize> {
+        if uncompressed_offset >= self.uncompressed_len {
+            return None;
+        }
+
+        // the index of the first block starting after `uncompressed_offset`
+        let next = self
+            .blocks
+            .partition_point(|block| block.uncompressed_offset <= uncompressed_offset);
+        Some(next - 1)
+    }
+}
+
+impl IndexedBlock {
+    /// The offset, in bits from the start of the file, of the magic of this block
+    pub fn compressed_bit_offset(&self) -> u64 {
+        self.compressed_bit_offset
+    }
+
+    /// The offset, in the decompressed file, of the first byte of this block
+    pub fn uncompressed_offset(&self) -> u64 {
+        self.uncompressed_offset
+    }
+
+    /// The size of the decompressed content of this block
+    pub fn uncompressed_len(&self) -> u32 {
+        self.uncompressed_len
+    }
+
+    /// The CRC of the decompressed content of this block
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::block::max_uncompressed_size;
+
+    fn build_chunked(compressed: &[u8], chunk_size: usize) -> BlockIndex {
+        let mut builder = BlockIndexBuilder::new();
+        for chunk in compressed.chunks(chunk_size) {
+            builder.write(chunk).unwrap();
+        }
+        builder.finish().unwrap()
+    }
+
+    #[test]
+    fn sample2() {
+        let compressed = include_bytes!("../../../tests/samplefiles/sample2.bz2");
+
+        let index = BlockIndex::build(compressed.as_ref()).unwrap();
+        assert_eq!(index.header().raw_blocksize(), 2);
+        assert_eq!(index.uncompressed_len(), 212340);
+
+        let blocks = index.blocks();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].compressed_bit_offset(), 32);
+        assert_eq!(blocks[0].uncompressed_offset(), 0);
+        assert_eq!(blocks[1].compressed_bit_offset(), 544888);
+        for pair in blocks.windows(2) {
+            assert_eq!(
+                pair[0].uncompressed_offset() + u64::from(pair[0].uncompressed_len()),
+                pair[1].uncompressed_offset()
+            );
+        }
+
+        assert_eq!(index.find(0), Some(0));
+        assert_eq!(index.find(u64::from(blocks[0].uncompressed_len())), Some(1));
+        assert_eq!(index.find(212339), Some(1));
+        assert_eq!(index.find(212340), None);
+
+        for &chunk_size in [1, 7, 1000].iter() {
+            let chunked = build_chunked(compressed, chunk_size);
+            assert_eq!(chunked.blocks(), index.blocks());
+        }
+    }
+
+    #[test]
+    fn multiple_streams() {
+        let mut compressed = include_bytes!("../../../tests/samplefiles/sample3.bz2").to_vec();
+        let second_stream = compressed.len() as u64 * 8;
+        compressed.extend_from_slice(include_bytes!("../../../tests/samplefiles/sample1.bz2"));
+        compressed.extend_from_slice(b"trailing garbage");
+
+        let index = build_chunked(&compressed, 4096);
+        assert_eq!(index.header().raw_blocksize(), 3);
+        assert_eq!(index.uncompressed_len(), 120244 + 98696);
+
+        let blocks = index.blocks();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[1].compressed_bit_offset(), second_stream + 32);
+        assert_eq!(blocks[1].uncompressed_offset(), 120244);
+    }
+
+    #[test]
+    fn truncated() {
+        let compressed = include_bytes!("../../../tests/samplefiles/sample2.bz2");
+
+        let err = BlockIndex::build(&compressed[..compressed.len() - 5])
+            .err()
+            .expect("the file is truncated");
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn serialize_roundtrip() {
+        let mut compressed = include_bytes!("../../../tests/samplefiles/sample3.bz2").to_vec();
+        compressed.extend_from_slice(include_bytes!("../../../tests/samplefiles/sample2.bz2"));
+        let index = BlockIndex::build(compressed.as_slice()).unwrap();
+
+        let mut serialized = Vec::new();
+        index.write_to(&mut serialized).unwrap();
+        assert_eq!(serialized.len(), 14 + 3 * 16);
+
+        let deserialized = BlockIndex::read_from(serialized.as_slice()).unwrap();
+        assert_eq!(deserialized.header().raw_blocksize(), 3);
+        assert_eq!(deserialized.blocks(), index.blocks());
+        assert_eq!(deserialized.uncompressed_len(), index.uncompressed_len());
+    }
+
+    #[test]
+    fn serialize_invalid() {
+        let compressed = include_bytes!("../../../tests/samplefiles/sample2.bz2");
+        let index = BlockIndex::build(compressed.as_ref()).unwrap();
+
+        let mut serialized = Vec::new();
+        index.write_to(&mut serialized).unwrap();
+
+        let read_err = |serialized: &[u8]| {
+            BlockIndex::read_from(serialized)
+                .err()
+                .expect("the index is invalid")
+        };
+
+        let mut bad_magic = serialized.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(read_err(&bad_magic).kind(), io::ErrorKind::InvalidData);
+
+        let mut bad_version = serialized.clone();
+        bad_version[4] = 2;
+        assert_eq!(
+            read_err(&bad_version).to_string(),
+            "unsupported block index version"
+        );
+
+        let mut bad_blocksize = serialized.clone();
+        bad_blocksize[5] = 0;
+        assert_eq!(read_err(&bad_blocksize).kind(), io::ErrorKind::InvalidData);
+
+        // the uncompressed size of the first block
+        let mut bad_uncompressed_len = serialized.clone();
+        bad_uncompressed_len[14 + 8..14 + 12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            read_err(&bad_uncompressed_len).to_string(),
+            "block index block size is too big"
+        );
+
+        // a block can't decode to more than 259 bytes every 5 bytes of its blocksize
+        let max_len = max_uncompressed_size(index.header());
+        let mut max_uncompressed_len = serialized.clone();
+        max_uncompressed_len[14 + 8..14 + 12].copy_from_slice(&max_len.to_le_bytes());
+        BlockIndex::read_from(max_uncompressed_len.as_slice()).unwrap();
+        max_uncompressed_len[14 + 8..14 + 12].copy_from_slice(&(max_len + 1).to_le_bytes());
+        assert_eq!(
+            read_err(&max_uncompressed_len).to_string(),
+            "block index block size is too big"
+        );
+
+        let truncated = &serialized[..serialized.len() - 1];
+        assert_eq!(read_err(truncated).kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
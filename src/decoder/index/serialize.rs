@@ -0,0 +1,133 @@
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+
+use super::{BlockIndex, IndexedBlock};
+use crate::decoder::block::max_uncompressed_size;
+use crate::header::Header;
+
+/// The magic value at the start of a serialized [`BlockIndex`]
+const MAGIC: [u8; 4] = *b"BZix";
+/// The version of the serialization format
+///
+/// It must be incremented every time the format changes.
+const VERSION: u8 = 1;
+
+// compressed bit offset + uncompressed length + crc
+const BLOCK_LEN: usize = 8 + 4 + 4;
+
+impl BlockIndex {
+    /// Serialize this [`BlockIndex`] into `writer`
+    ///
+    /// The serialized index can be read back through [`BlockIndex::read_from`],
+    /// avoiding having to decode the whole file again in order to rebuild it.
+    ///
+    /// The format is made of:
+    ///
+    /// * the 4 bytes magic value `BZix`
+    /// * the version of the format, as a byte, currently `1`
+    /// * the raw blocksize of [`BlockIndex::header`], as a byte
+    /// * the number of blocks, as a little-endian `u64`
+    /// * for every block the compressed bit offset, as a little-endian `u64`,
+    ///   followed by the uncompressed length and the CRC, as little-endian `u32`s
+    ///
+    /// ```rust
+    /// use bzip2_rs::decoder::BlockIndex;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let compressed_file: &[u8] = include_bytes!("../../../tests/samplefiles/sample2.bz2").as_ref();
+    /// let index = BlockIndex::build(compressed_file)?;
+    ///
+    /// let mut serialized = Vec::new();
+    /// index.write_to(&mut serialized)?;
+    ///
+    /// let deserialized = BlockIndex::read_from(serialized.as_slice())?;
+    /// assert_eq!(deserialized.blocks(), index.blocks());
+    /// #
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[VERSION, self.header.raw_blocksize()])?;
+        writer.write_all(&(self.blocks.len() as u64).to_le_bytes())?;
+
+        for block in &self.blocks {
+            let mut buf = [0; BLOCK_LEN];
+            buf[..8].copy_from_slice(&block.compressed_bit_offset.to_le_bytes());
+            buf[8..12].copy_from_slice(&block.uncompressed_len.to_le_bytes());
+            buf[12..].copy_from_slice(&block.crc.to_le_bytes());
+            writer.write_all(&buf)?;
+        }
+
+        Ok(())
+    }
+
+    /// Deserialize a [`BlockIndex`] written by [`BlockIndex::write_to`]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidData`] if the data
+    /// isn't a serialized [`BlockIndex`], if it was written by an
+    /// unsupported version of the format or if one of its blocks is
+    /// bigger than a block can be. The initial run-length encoding lets every
+    /// 5 bytes of the blocksize decode to at most 259 bytes.
+    ///
+    /// The index isn't checked against the file it was built from. Blocks whose
+    /// CRC doesn't match the one stored in the index are instead reported as
+    /// errors by [`SeekableDecoderReader`] when they're decoded.
+    ///
+    /// [`SeekableDecoderReader`]: crate::decoder::SeekableDecoderReader
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut preamble = [0; 4 + 1 + 1 + 8];
+        reader.read_exact(&mut preamble)?;
+
+        if preamble[..4] != MAGIC {
+            return Err(invalid_data("not a serialized block index"));
+        }
+        if preamble[4] != VERSION {
+            return Err(invalid_data("unsupported block index version"));
+        }
+        let header = Header::from_raw_blocksize(preamble[5])
+            .map_err(|_| invalid_data("invalid block index blocksize"))?;
+        let len = u64::from_le_bytes(preamble[6..].try_into().unwrap());
+
+        // don't trust `len` for preallocating
+        let mut blocks = Vec::with_capacity(len.min(4096) as usize);
+        let mut uncompressed_len = 0u64;
+        for _ in 0..len {
+            let mut buf = [0; BLOCK_LEN];
+            reader.read_exact(&mut buf)?;
+
+            let block = IndexedBlock {
+                compressed_bit_offset: u64::from_le_bytes(buf[..8].try_into().unwrap()),
+                uncompressed_offset: uncompressed_len,
+                uncompressed_len: u32::from_le_bytes(buf[8..12].try_into().unwrap()),
+                crc: u32::from_le_bytes(buf[12..].try_into().unwrap()),
+            };
+
+            let is_sorted = blocks.last().map_or(true, |prev: &IndexedBlock| {
+                block.compressed_bit_offset > prev.compressed_bit_offset
+            });
+            if !is_sorted {
+                return Err(invalid_data("block index offsets aren't increasing"));
+            }
+            // also keeps `SeekableDecoderReader` from allocating a buffer of any size
+            if block.uncompressed_len > max_uncompressed_size(&header) {
+                return Err(invalid_data("block index block size is too big"));
+            }
+
+            uncompressed_len += u64::from(block.uncompressed_len);
+            blocks.push(block);
+        }
+
+        Ok(Self {
+            header,
+            blocks,
+            uncompressed_len,
+        })
+    }
+}
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
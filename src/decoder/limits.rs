@@ -0,0 +1,109 @@
+use super::DecoderError;
+
+/// Limits on the resources used for decoding untrusted data
+///
+/// By default nothing is limited. Exceeding a limit makes the decoder
+/// return [`DecoderError::LimitExceeded`].
+///
+/// ```rust
+/// use bzip2_rs::decoder::Limits;
+///
+/// let limits = Limits::new()
+///     .max_uncompressed_len(512 * 1024 * 1024)
+///     .max_streams(1);
+/// ```
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Limits {
+    max_uncompressed_len: Option<u64>,
+    max_blocks: Option<u64>,
+    max_streams: Option<u32>,
+    pub(crate) max_buffered_memory: Option<usize>,
+}
+
+/// A limit set through [`Limits`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Limit {
+    /// The limit set through [`Limits::max_uncompressed_len`]
+    UncompressedLen,
+    /// The limit set through [`Limits::max_blocks`]
+    Blocks,
+    /// The limit set through [`Limits::max_streams`]
+    Streams,
+    /// The limit set through [`Limits::max_buffered_memory`]
+    BufferedMemory,
+}
+
+impl Limits {
+    /// Construct new [`Limits`], not limiting anything
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit the size of the decompressed data
+    pub fn max_uncompressed_len(mut self, max_uncompressed_len: u64) -> Self {
+        self.max_uncompressed_len = Some(max_uncompressed_len);
+        self
+    }
+
+    /// Limit the number of blocks, across all streams
+    pub fn max_blocks(mut self, max_blocks: u64) -> Self {
+        self.max_blocks = Some(max_blocks);
+        self
+    }
+
+    /// Limit the number of concatenated streams
+    pub fn max_streams(mut self, max_streams: u32) -> Self {
+        self.max_streams = Some(max_streams);
+        self
+    }
+
+    /// Limit the memory, in bytes, used by [`ParallelDecoder`] for buffering
+    /// compressed data and decoding blocks
+    ///
+    /// Every block being decoded, or waiting to be read, counts towards this
+    /// limit with its buffer, which takes 4 times the block size of the stream,
+    /// and its pre-read budget. So do the unused buffers held by its [`BufferPool`],
+    /// which are deallocated when the rest of the memory is needed.
+    ///
+    /// No more blocks are scheduled for decoding once this limit is reached,
+    /// until the ones before them have been read, and the pre-read budget is shrunk
+    /// in order to respect it. This limit is then only exceeded when a single block,
+    /// or the compressed data which has to be buffered for finding the next one,
+    /// doesn't fit in it. Since a block can take as much as about 5 MB,
+    /// setting a lower value is discouraged.
+    ///
+    /// [`Decoder`] doesn't buffer more than what's written into it,
+    /// so it ignores this limit.
+    ///
+    /// [`Decoder`]: crate::decoder::Decoder
+    /// [`ParallelDecoder`]: crate::decoder::ParallelDecoder
+    /// [`BufferPool`]: crate::decoder::BufferPool
+    pub fn max_buffered_memory(mut self, max_buffered_memory: usize) -> Self {
+        self.max_buffered_memory = Some(max_buffered_memory);
+        self
+    }
+
+    pub(crate) fn check_uncompressed_len(&self, len: u64) -> Result<(), DecoderError> {
+        check(self.max_uncompressed_len, len, Limit::UncompressedLen)
+    }
+
+    pub(crate) fn check_blocks(&self, blocks: u64) -> Result<(), DecoderError> {
+        check(self.max_blocks, blocks, Limit::Blocks)
+    }
+
+    pub(crate) fn check_streams(&self, streams: u32) -> Result<(), DecoderError> {
+        check(self.max_streams, streams, Limit::Streams)
+    }
+
+    pub(crate) fn check_buffered_memory(&self, len: usize) -> Result<(), DecoderError> {
+        check(self.max_buffered_memory, len, Limit::BufferedMemory)
+    }
+}
+
+fn check<T: PartialOrd>(max: Option<T>, value: T, limit: Limit) -> Result<(), DecoderError> {
+    match max {
+        Some(max) if value > max => Err(DecoderError::LimitExceeded(limit)),
+        _ => Ok(()),
+    }
+}
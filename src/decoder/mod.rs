@@ -2,20 +2,41 @@
 
 use std::collections::VecDeque;
 use std::convert::TryInto;
+use std::mem;
 
-use self::block::Block;
+use self::block::{
+    max_compressed_size, Block, BlockError, BlockErrorKind, Location, BLOCK_MAGIC, FINAL_MAGIC,
+};
+pub use self::decompress::{decompress, decompress_into, decompress_parallel};
 pub use self::error::DecoderError;
+pub use self::index::{BlockIndex, BlockIndexBuilder, IndexedBlock};
+pub use self::limits::{Limit, Limits};
+pub use self::options::DecoderOptions;
+use self::parallel::scanner::iter::SignatureFinder;
 pub use self::parallel::{ParallelDecoder, ParallelDecoderReader};
+pub use self::pool::BufferPool;
 pub use self::reader::DecoderReader;
+pub use self::recover::{recover_blocks, RecoverBlocks, RecoveredBlock};
+pub use self::seekable::SeekableDecoderReader;
 pub use self::state::ReadState;
+pub use self::verify::{verify_reader, VerifySummary};
 use crate::bitreader::BitReader;
+use crate::crc;
 use crate::header::Header;
 
 pub mod block;
+mod decompress;
 mod error;
+mod index;
+mod limits;
+mod options;
 mod parallel;
+mod pool;
 mod reader;
+mod recover;
+mod seekable;
 mod state;
+mod verify;
 
 /// A low-level **single-threaded** decoder implementation
 ///
@@ -48,9 +69,10 @@ mod state;
 ///             // `Decoder` needs more data to be written to it before it
 ///             // can decode the next block.
 ///             // If we reached the end of the file `compressed_file.len()` will be 0,
-///             // signaling to the `Decoder` that the last block is smaller and it can
+///             // signaling to the `Decoder` that no more data is coming and it can
 ///             // proceed with reading.
 ///             decoder.write(&compressed_file);
+///             compressed_file = &[];
 ///         }
 ///         ReadState::Read(n) => {
 ///             // `n` uncompressed bytes have been read into `buf`
@@ -75,6 +97,34 @@ pub struct Decoder {
 
     skip_bits: usize,
     in_buf: VecDeque<u8>,
+    // number of bits drained from `in_buf`
+    consumed_bits: u64,
+    // the position, in bytes into `in_buf`, from which to continue looking
+    // for the magic following the block which hasn't been fully written yet
+    scan_pos: usize,
+
+    // the location of the block being read
+    location: Location,
+    // the combined crc of the blocks read from the current stream
+    stream_crc: u32,
+    verify_crc: bool,
+
+    skip_corrupt_blocks: bool,
+    // the errors of the blocks which have been skipped
+    skipped_errors: Vec<DecoderError>,
+    // the bit position in `in_buf` from which to look for the magic
+    // of the block following a corrupt one
+    resync_from: Option<usize>,
+    // a block of the current stream has been skipped
+    stream_damaged: bool,
+
+    multi_stream: bool,
+    // number of streams which have been fully read
+    streams: u32,
+
+    limits: Limits,
+    summary: VerifySummary,
+    buffer_pool: BufferPool,
 
     eof: bool,
     write_eof: bool,
@@ -83,11 +133,38 @@ pub struct Decoder {
 impl Decoder {
     /// Construct a new [`Decoder`], ready to decompress a new bzip2 file
     pub fn new() -> Self {
+        Self::with_options(DecoderOptions::new())
+    }
+
+    /// Construct a new [`Decoder`] configured through `options`,
+    /// ready to decompress a new bzip2 file
+    pub fn with_options(options: DecoderOptions) -> Self {
         Self {
             header_block: None,
 
             skip_bits: 0,
             in_buf: VecDeque::new(),
+            consumed_bits: 0,
+            scan_pos: 0,
+
+            location: Location {
+                bit_offset: 0,
+                block_index: 0,
+            },
+            stream_crc: 0,
+            verify_crc: options.verify_crc,
+
+            skip_corrupt_blocks: options.skip_corrupt_blocks,
+            skipped_errors: Vec::new(),
+            resync_from: None,
+            stream_damaged: false,
+
+            multi_stream: options.multi_stream,
+            streams: 0,
+
+            limits: options.limits,
+            summary: VerifySummary::default(),
+            buffer_pool: BufferPool::new(),
 
             eof: false,
             write_eof: false,
@@ -103,80 +180,396 @@ impl Decoder {
         }
     }
 
+    /// Set whether to continue decoding into the next stream once the
+    /// end of the current one is reached
+    ///
+    /// Files produced by tools like `pbzip2`, or by concatenating multiple
+    /// bzip2 files, contain several bzip2 streams back-to-back. When enabled,
+    /// which is the default, they are transparently decoded as a single file
+    /// and any trailing data which isn't a valid bzip2 stream is ignored,
+    /// like `bzip2 -d` does. When disabled, decoding stops at the end of the
+    /// first stream.
+    pub fn set_multi_stream(&mut self, multi_stream: bool) {
+        self.multi_stream = multi_stream;
+    }
+
+    /// Set the [`Limits`] on the resources used for decoding
+    ///
+    /// Exceeding one of them makes [`Decoder::read`] return [`DecoderError::LimitExceeded`].
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Set whether to skip the blocks which can't be decoded,
+    /// instead of returning an error
+    ///
+    /// When enabled, the error of a damaged block is recorded in
+    /// [`Decoder::skipped_errors`] and decoding resumes from the next
+    /// block magic value found after it. Mismatching stream CRCs are recorded
+    /// in the same way, unless they are caused by a skipped block.
+    /// This is disabled by default.
+    ///
+    /// Blocks are decoded while they are being read, so the damaged
+    /// block may have already been partially returned by [`Decoder::read`]
+    /// by the time its CRC is found to be wrong.
+    pub fn set_skip_corrupt_blocks(&mut self, skip_corrupt_blocks: bool) {
+        self.skip_corrupt_blocks = skip_corrupt_blocks;
+    }
+
+    /// Set the [`BufferPool`] the buffers for decoding blocks are taken from
+    ///
+    /// See [`BufferPool`] for more details.
+    pub fn set_buffer_pool(&mut self, buffer_pool: BufferPool) {
+        self.buffer_pool = buffer_pool;
+    }
+
+    /// The errors of the blocks skipped because of
+    /// [`Decoder::set_skip_corrupt_blocks`], in order
+    pub fn skipped_errors(&self) -> &[DecoderError] {
+        &self.skipped_errors
+    }
+
+    /// The data written to this [`Decoder`] which hasn't been decoded yet
+    ///
+    /// Once [`ReadState::Eof`] has been returned, this is the data following
+    /// the end of the first stream if [`Decoder::set_multi_stream`] was disabled,
+    /// or the trailing data which isn't a valid bzip2 stream otherwise.
+    pub fn remaining_input(&mut self) -> &[u8] {
+        self.in_buf.make_contiguous()
+    }
+
     /// Read more decompressed data from this [`Decoder`]
     ///
     /// See the documentation for [`ReadState`] to decide
     /// what to do next.
     pub fn read(&mut self, buf: &mut [u8]) -> Result<ReadState, DecoderError> {
-        match &mut self.header_block {
-            Some(_) if self.eof => Ok(ReadState::Eof),
-            Some((header, block)) if block.is_not_ready() => {
-                let minimum = (self.skip_bits / 8) + header.max_blocksize() as usize;
-                if self.write_eof || self.in_buf.len() >= minimum {
-                    block.set_ready_for_read();
-                    self.read(buf)
-                } else {
-                    Ok(ReadState::NeedsWrite)
+        if self.eof {
+            return Ok(ReadState::Eof);
+        }
+
+        loop {
+            if let Some(start) = self.resync_from {
+                if !self.resync(start) {
+                    return if self.write_eof {
+                        // no more blocks after the corrupt one
+                        self.eof = true;
+                        Ok(ReadState::Eof)
+                    } else {
+                        Ok(ReadState::NeedsWrite)
+                    };
                 }
             }
-            Some((_, block)) => {
-                let mut reader = {
-                    debug_assert!(self.skip_bits < 8);
 
-                    let (slice1, slice2) = self.in_buf.as_slices();
-                    let mut reader = BitReader::new([slice1, slice2]);
+            match &mut self.header_block {
+                Some((header, block)) if block.is_not_ready() => {
+                    // no block can be bigger than this
+                    let minimum = (self.skip_bits / 8) + max_compressed_size(header);
+                    if self.write_eof
+                        || self.in_buf.len() >= minimum
+                        || is_block_written(&mut self.in_buf, self.skip_bits, &mut self.scan_pos)
+                    {
+                        block.set_ready_for_read();
+                        continue;
+                    }
+
+                    return Ok(ReadState::NeedsWrite);
+                }
+                Some((header, block)) => {
+                    let mut reader = {
+                        debug_assert!(self.skip_bits < 8);
 
-                    for _ in 0..self.skip_bits {
-                        reader.next().expect("enough bits");
+                        let (slice1, slice2) = self.in_buf.as_slices();
+                        let mut reader = BitReader::new([slice1, slice2]);
+
+                        reader.skip(self.skip_bits as u32).expect("enough bits");
+
+                        reader
+                    };
+
+                    let ready_for_read = block.is_ready_for_read();
+                    if ready_for_read {
+                        // the magic of the next block or of the end of stream marker
+                        self.location.bit_offset = self.consumed_bits + self.skip_bits as u64;
                     }
 
-                    reader
-                };
+                    let minimum = (self.skip_bits / 8) + max_compressed_size(header);
+
+                    let location = self.location;
+                    let read = match block.read(&mut reader, buf) {
+                        Ok(read) => read,
+                        Err(err)
+                            if !self.write_eof
+                                && self.in_buf.len() < minimum
+                                && matches!(err.kind(), BlockErrorKind::Truncated(_)) =>
+                        {
+                            // the magic following the block was found by chance
+                            // inside of its data, which hasn't been fully written yet
+                            return Ok(ReadState::NeedsWrite);
+                        }
+                        Err(err) if self.skip_corrupt_blocks => {
+                            // if the block hasn't been read yet `in_buf` still starts
+                            // from its magic, otherwise it has already been consumed
+                            let start = self.skip_bits + usize::from(ready_for_read);
+                            self.skip_block(err.with_location(location), start);
+                            continue;
+                        }
+                        Err(err) => return Err(err.with_location(location).into()),
+                    };
+
+                    if ready_for_read {
+                        self.scan_pos = 0;
+                    }
+                    if ready_for_read && read > 0 {
+                        // a new block has been decoded
+                        self.limits.check_blocks(self.summary.blocks + 1)?;
+                    }
+
+                    if read == 0 {
+                        if ready_for_read && !buf.is_empty() {
+                            // the end of stream marker has been read
+                            let expected = block.stream_crc();
+                            let actual = mem::replace(&mut self.stream_crc, 0);
+                            let damaged = mem::replace(&mut self.stream_damaged, false);
+                            if expected != actual && !damaged && self.verify_crc {
+                                let err = DecoderError::BadStreamCrc {
+                                    expected,
+                                    actual,
+                                    location,
+                                };
+                                if !self.skip_corrupt_blocks {
+                                    return Err(err);
+                                }
+
+                                self.skipped_errors.push(err);
+                            }
+                            self.location.block_index = 0;
 
-                let ready_for_read = block.is_ready_for_read();
+                            self.streams += 1;
+                            self.summary.stream_crcs.push(expected);
 
-                let read = block.read(&mut reader, buf)?;
+                            // the next stream starts at the next byte boundary
+                            let bytes_num = (reader.position() as usize + 7) / 8;
+                            self.consume(bytes_num.min(self.in_buf.len()));
+                            self.skip_bits = 0;
+                            self.header_block = None;
 
-                if read == 0 {
-                    if !buf.is_empty() {
-                        self.eof = ready_for_read;
+                            if !self.multi_stream {
+                                self.eof = true;
+                                return Ok(ReadState::Eof);
+                            }
+
+                            continue;
+                        }
+
+                        if !buf.is_empty() {
+                            // the block has been fully read and its crc verified
+                            self.stream_crc = crc::combine(self.stream_crc, block.crc());
+                            self.location.block_index += 1;
+                            self.summary.blocks += 1;
+
+                            // the next block may already have been written
+                            continue;
+                        }
+
+                        return Ok(ReadState::NeedsWrite);
                     }
 
-                    return Ok(ReadState::NeedsWrite);
+                    let bytes_num = reader.position() / 8;
+                    let bits_num = reader.position() % 8;
+
+                    self.consume(bytes_num as usize);
+                    self.skip_bits = bits_num as usize;
+                    self.summary.uncompressed_len += read as u64;
+                    self.limits
+                        .check_uncompressed_len(self.summary.uncompressed_len)?;
+
+                    return Ok(ReadState::Read(read));
                 }
+                None => {
+                    if self.in_buf.len() >= 4 {
+                        let header = {
+                            debug_assert_eq!(self.skip_bits, 0);
+                            self.in_buf.make_contiguous();
+                            let (slice1, _) = self.in_buf.as_slices();
+                            Header::parse(slice1[..4].try_into().unwrap())
+                        };
+                        let header = match header {
+                            Ok(header) => header,
+                            Err(_) if self.streams > 0 => {
+                                // trailing garbage after the last stream
+                                self.eof = true;
+                                return Ok(ReadState::Eof);
+                            }
+                            Err(err) => return Err(err.into()),
+                        };
+                        self.limits.check_streams(self.streams + 1)?;
+
+                        let mut block = Block::with_pool(header.clone(), &self.buffer_pool);
+                        block.set_verify_crc(self.verify_crc);
+                        self.header_block = Some((header, block));
 
-                if read == 0 && !buf.is_empty() {
-                    self.eof = true;
+                        debug_assert_eq!(self.skip_bits % 8, 0);
+                        self.consume(4);
+                    } else if self.write_eof && self.streams > 0 {
+                        // no more streams
+                        self.eof = true;
+                        return Ok(ReadState::Eof);
+                    } else {
+                        return Ok(ReadState::NeedsWrite);
+                    }
                 }
+            }
+        }
+    }
+
+    /// Decode the data written so far, checking its integrity
+    /// but throwing away the decompressed data
+    ///
+    /// This behaves like [`Decoder::read`], except for never returning
+    /// [`ReadState::Read`]: [`ReadState::NeedsWrite`] is returned when
+    /// more data has to be written and [`ReadState::Eof`] once the end
+    /// of the file has been reached, after which [`Decoder::summary`]
+    /// describes the whole file.
+    pub fn verify(&mut self) -> Result<ReadState, DecoderError> {
+        let mut buf = [0; 8 * 1024];
+        loop {
+            match self.read(&mut buf)? {
+                ReadState::Read(_) => {}
+                state => return Ok(state),
+            }
+        }
+    }
+
+    /// A summary of the data decoded so far
+    pub fn summary(&self) -> &VerifySummary {
+        &self.summary
+    }
+
+    /// Reset this [`Decoder`], making it ready to decompress a new bzip2 file
+    ///
+    /// Any data written to it and not yet decoded is discarded, while
+    /// the options it has been configured with are kept. Unlike constructing
+    /// a new [`Decoder`], the buffers which have already been allocated
+    /// are reused, making this cheaper when decoding many small files.
+    pub fn reset(&mut self) {
+        // the buffer of the block goes back to `buffer_pool`
+        self.header_block = None;
+
+        self.skip_bits = 0;
+        self.in_buf.clear();
+        self.consumed_bits = 0;
+        self.scan_pos = 0;
+
+        self.location = Location {
+            bit_offset: 0,
+            block_index: 0,
+        };
+        self.stream_crc = 0;
+
+        self.skipped_errors.clear();
+        self.resync_from = None;
+        self.stream_damaged = false;
 
-                let bytes_num = reader.position() / 8;
-                let bits_num = reader.position() % 8;
+        self.streams = 0;
 
-                self.in_buf.drain(..bytes_num as usize);
-                self.skip_bits = bits_num as usize;
+        self.summary.blocks = 0;
+        self.summary.uncompressed_len = 0;
+        self.summary.stream_crcs.clear();
 
-                Ok(ReadState::Read(read))
+        self.eof = false;
+        self.write_eof = false;
+    }
+
+    /// Record the error of a corrupt block and prepare for looking
+    /// for the next block, from `start` bits into `in_buf`
+    fn skip_block(&mut self, err: BlockError, start: usize) {
+        self.skipped_errors.push(err.into());
+        self.stream_damaged = true;
+        self.location.block_index += 1;
+
+        if let Some((header, block)) = self.header_block.take() {
+            // give the buffer of the corrupt block back, so that it can be reused
+            drop(block);
+
+            let mut block = Block::with_pool(header.clone(), &self.buffer_pool);
+            block.set_verify_crc(self.verify_crc);
+            self.header_block = Some((header, block));
+        }
+        self.resync_from = Some(start);
+        self.scan_pos = 0;
+    }
+
+    /// Look for the magic of the next block, or of the end of the stream,
+    /// from `start` bits into `in_buf`
+    ///
+    /// Returns `false` if more data is needed.
+    fn resync(&mut self, start: usize) -> bool {
+        let signature = {
+            self.in_buf.make_contiguous();
+            let (slice1, _) = self.in_buf.as_slices();
+            SignatureFinder::new(slice1).find(|&signature| signature >= start as u64)
+        };
+
+        match signature {
+            Some(signature) => {
+                self.consume((signature / 8) as usize);
+                self.skip_bits = (signature % 8) as usize;
+                self.resync_from = None;
+                true
             }
             None => {
-                if self.in_buf.len() >= 4 {
-                    let header = {
-                        debug_assert_eq!(self.skip_bits, 0);
-                        let (slice1, slice2) = self.in_buf.as_slices();
-                        debug_assert!(slice1.len() >= 4);
-                        debug_assert!(slice2.is_empty());
-                        Header::parse(slice1[..4].try_into().unwrap())?
-                    };
-                    let block = Block::new(header.clone());
-                    self.header_block = Some((header, block));
+                // a magic could start in the last 6 bytes
+                // and continue in the next write
+                let drop = self.in_buf.len().saturating_sub(6).min(start / 8);
+                self.consume(drop);
+                self.resync_from = Some(start - drop * 8);
+                false
+            }
+        }
+    }
 
-                    debug_assert_eq!(self.skip_bits % 8, 0);
-                    self.in_buf.drain(..4);
+    /// Drain `bytes` bytes from `in_buf`
+    fn consume(&mut self, bytes: usize) {
+        self.in_buf.drain(..bytes);
+        self.consumed_bits += bytes as u64 * 8;
+    }
+}
 
-                    self.read(buf)
-                } else {
-                    Ok(ReadState::NeedsWrite)
-                }
-            }
+/// Whether the block, or the end of stream marker, starting `skip_bits`
+/// into `in_buf` has been fully written, scanning `in_buf` from `scan_pos`
+/// for the magic following it
+fn is_block_written(in_buf: &mut VecDeque<u8>, skip_bits: usize, scan_pos: &mut usize) -> bool {
+    let buf = &*in_buf.make_contiguous();
+
+    let mut reader = BitReader::new([buf, &[]]);
+    reader.skip(skip_bits as u32).expect("enough bits");
+    match reader.read_u64(48) {
+        Some(BLOCK_MAGIC) => {}
+        // the end of stream marker is only followed by the stream crc
+        Some(FINAL_MAGIC) => return reader.read_u32(32).is_some(),
+        // decoding reports the bad magic
+        Some(_) => return true,
+        None => return false,
+    }
+
+    // skip signatures found by chance before the end of the magic
+    let after_magic = skip_bits as u64 + 48;
+    let start = (*scan_pos).max((after_magic / 8) as usize);
+    let signature = SignatureFinder::new(&buf[start..])
+        .map(|signature| start as u64 * 8 + signature)
+        .find(|&signature| signature >= after_magic);
+
+    match signature {
+        Some(signature) => {
+            // if it was found by chance, look for the next one once more data is written
+            *scan_pos = (signature / 8) as usize + 1;
+            true
+        }
+        None => {
+            // a magic could start in the last 6 bytes
+            // and continue in the next write
+            *scan_pos = buf.len().saturating_sub(6).max(start);
+            false
         }
     }
 }
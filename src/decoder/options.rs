@@ -0,0 +1,127 @@
+use super::Limits;
+
+/// Options shared by [`Decoder`], [`DecoderReader`], [`ParallelDecoder`]
+/// and [`ParallelDecoderReader`]
+///
+/// Options which don't apply to a decoder are ignored by it.
+///
+/// ```rust
+/// use bzip2_rs::decoder::{DecoderOptions, Limits};
+/// use bzip2_rs::DecoderReader;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let compressed_file: &[u8] = include_bytes!("../../tests/samplefiles/sample1.bz2").as_ref();
+///
+/// let options = DecoderOptions::new()
+///     .multi_stream(false)
+///     .limits(Limits::new().max_uncompressed_len(1024 * 1024))
+///     .input_buf_len(64 * 1024);
+/// let reader = DecoderReader::with_options(compressed_file, options);
+/// #
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Decoder`]: crate::decoder::Decoder
+/// [`DecoderReader`]: crate::decoder::DecoderReader
+/// [`ParallelDecoder`]: crate::decoder::ParallelDecoder
+/// [`ParallelDecoderReader`]: crate::decoder::ParallelDecoderReader
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DecoderOptions {
+    pub(crate) multi_stream: bool,
+    pub(crate) skip_corrupt_blocks: bool,
+    pub(crate) verify_crc: bool,
+    pub(crate) limits: Limits,
+    pub(crate) input_buf_len: usize,
+    pub(crate) max_preread_len: usize,
+}
+
+impl DecoderOptions {
+    /// Construct the default [`DecoderOptions`]
+    pub fn new() -> Self {
+        Self {
+            multi_stream: true,
+            skip_corrupt_blocks: false,
+            verify_crc: true,
+            limits: Limits::new(),
+            input_buf_len: 1024,
+            max_preread_len: 1024 * 1024,
+        }
+    }
+
+    /// Set whether to continue decoding into the next stream once the
+    /// end of the current one is reached
+    ///
+    /// Enabled by default. See [`Decoder::set_multi_stream`] for more details.
+    ///
+    /// [`Decoder::set_multi_stream`]: crate::decoder::Decoder::set_multi_stream
+    pub fn multi_stream(mut self, multi_stream: bool) -> Self {
+        self.multi_stream = multi_stream;
+        self
+    }
+
+    /// Set whether to skip the blocks which can't be decoded,
+    /// instead of returning an error
+    ///
+    /// Disabled by default. See [`Decoder::set_skip_corrupt_blocks`] for more details.
+    ///
+    /// [`Decoder::set_skip_corrupt_blocks`]: crate::decoder::Decoder::set_skip_corrupt_blocks
+    pub fn skip_corrupt_blocks(mut self, skip_corrupt_blocks: bool) -> Self {
+        self.skip_corrupt_blocks = skip_corrupt_blocks;
+        self
+    }
+
+    /// Set whether to verify the CRC of every block and stream
+    ///
+    /// Enabled by default. Disabling it speeds up decoding, but corrupted
+    /// data may then be returned without any error, so it should only be
+    /// done for data which is already protected by other means, like an
+    /// outer checksum.
+    pub fn verify_crc(mut self, verify_crc: bool) -> Self {
+        self.verify_crc = verify_crc;
+        self
+    }
+
+    /// Set the [`Limits`] on the resources used for decoding
+    ///
+    /// Nothing is limited by default.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Set how many bytes are read at a time from the underlying reader
+    ///
+    /// Defaults to 1 KiB. Only used by [`DecoderReader`] and [`ParallelDecoderReader`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `input_buf_len` is `0`.
+    ///
+    /// [`DecoderReader`]: crate::decoder::DecoderReader
+    /// [`ParallelDecoderReader`]: crate::decoder::ParallelDecoderReader
+    pub fn input_buf_len(mut self, input_buf_len: usize) -> Self {
+        assert!(input_buf_len > 0, "input_buf_len must be greater than 0");
+        self.input_buf_len = input_buf_len;
+        self
+    }
+
+    /// Set how many bytes can be pre-read from the blocks being decoded
+    ///
+    /// Defaults to 1 MiB. Only used by [`ParallelDecoder`] and [`ParallelDecoderReader`].
+    /// See [`ParallelDecoder::new`] for more details.
+    ///
+    /// [`ParallelDecoder`]: crate::decoder::ParallelDecoder
+    /// [`ParallelDecoder::new`]: crate::decoder::ParallelDecoder::new
+    /// [`ParallelDecoderReader`]: crate::decoder::ParallelDecoderReader
+    pub fn max_preread_len(mut self, max_preread_len: usize) -> Self {
+        self.max_preread_len = max_preread_len;
+        self
+    }
+}
+
+impl Default for DecoderOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
 use std::convert::TryInto;
 use std::mem;
 use std::sync::mpsc::{channel, Receiver, Sender};
@@ -8,17 +8,20 @@ pub use self::reader::ParallelDecoderReader;
 use self::scanner::threaded::find_signatures_parallel;
 use self::util::ReadableVec;
 use crate::bitreader::BitReader;
-use crate::decoder::block::{Block, BlockError, FINAL_MAGIC};
-use crate::decoder::{DecoderError, ReadState};
+use crate::crc;
+use crate::decoder::block::{Block, BlockError, BlockErrorKind, Field, Location, FINAL_MAGIC};
+use crate::decoder::{BufferPool, DecoderError, DecoderOptions, Limits, ReadState, VerifySummary};
 use crate::header::Header;
 use crate::ThreadPool;
 
 mod reader;
-mod scanner;
+pub(crate) mod scanner;
 mod util;
 
-/// (block index, Result<(`PreRead` Block, Block)>)
-type ChannelledBlock = (u32, Result<(ReadableVec, Block), BlockError>);
+/// Result<(`PreRead` Block, Block, Block Location)>
+type DecodedBlock = Result<(ReadableVec, Block, Location), BlockError>;
+/// (block index, `DecodedBlock`)
+type ChannelledBlock = (u32, DecodedBlock);
 
 /// A low-level **multi-threaded** decoder implementation
 ///
@@ -97,6 +100,8 @@ pub struct ParallelDecoder<P> {
     header: Option<Header>,
     in_buf: Vec<u8>,
     skip_bits: usize,
+    // the offset, in bits, of the start of `in_buf` in the compressed data
+    in_buf_offset: u64,
 
     pool: P,
     sender: Sender<ChannelledBlock>,
@@ -105,11 +110,41 @@ pub struct ParallelDecoder<P> {
     next_index: u32,
     // the next block index expected to be received
     receive_index: u32,
-    receive_pool: BTreeMap<u32, Option<(ReadableVec, Block)>>,
+    receive_pool: BTreeMap<u32, Option<DecodedBlock>>,
+    // the index, within the current stream, of the next block to be scheduled
+    stream_block_index: u32,
 
     max_preread_len: usize,
+    // the memory reserved by every block in `receive_pool`, in order:
+    // its `tt` buffer and its pre-read budget
+    block_budgets: VecDeque<usize>,
+    // the sum of `block_budgets`
+    reserved_memory: usize,
+    // number of blocks which have been scheduled, across all streams
+    scheduled_blocks: u64,
+    buffer_pool: BufferPool,
+
+    // the combined crc of the blocks read from the current stream
+    stream_crc: u32,
+    verify_crc: bool,
+    // (index of the first block after the end of the stream, stream crc, end of stream location)
+    stream_ends: VecDeque<(u32, u32, Location)>,
+
+    skip_corrupt_blocks: bool,
+    // the errors of the blocks which have been skipped
+    skipped_errors: Vec<DecoderError>,
+    // a block of the current stream has been skipped
+    stream_damaged: bool,
+
+    multi_stream: bool,
+    // number of streams whose end has been found
+    streams: u32,
+
+    limits: Limits,
+    summary: VerifySummary,
 
     eof: bool,
+    write_eof: bool,
 }
 
 impl<P> ParallelDecoder<P> {
@@ -136,12 +171,21 @@ impl<P> ParallelDecoder<P> {
     /// [`Decoder`]: crate::decoder::Decoder
     /// [`RayonThreadPool`]: crate::RayonThreadPool
     pub fn new(pool: P, max_preread_len: usize) -> Self {
+        Self::with_options(pool, DecoderOptions::new().max_preread_len(max_preread_len))
+    }
+
+    /// Construct a new [`ParallelDecoder`] configured through `options`,
+    /// ready to decompress a new bzip2 file
+    ///
+    /// See [`ParallelDecoder::new`] for more details.
+    pub fn with_options(pool: P, options: DecoderOptions) -> Self {
         let (sender, receiver) = channel::<ChannelledBlock>();
 
         Self {
             header: None,
             in_buf: Vec::new(),
             skip_bits: 0,
+            in_buf_offset: 0,
 
             pool,
             sender,
@@ -149,76 +193,234 @@ impl<P> ParallelDecoder<P> {
             next_index: 0,
             receive_index: 0,
             receive_pool: BTreeMap::new(),
+            stream_block_index: 0,
+
+            max_preread_len: options.max_preread_len,
+            block_budgets: VecDeque::new(),
+            reserved_memory: 0,
+            scheduled_blocks: 0,
+            buffer_pool: BufferPool::new(),
+
+            stream_crc: 0,
+            verify_crc: options.verify_crc,
+            stream_ends: VecDeque::new(),
+
+            skip_corrupt_blocks: options.skip_corrupt_blocks,
+            skipped_errors: Vec::new(),
+            stream_damaged: false,
+
+            multi_stream: options.multi_stream,
+            streams: 0,
 
-            max_preread_len,
+            limits: options.limits,
+            summary: VerifySummary::default(),
 
             eof: false,
+            write_eof: false,
         }
     }
+
+    /// Set whether to continue decoding into the next stream once the
+    /// end of the current one is reached
+    ///
+    /// See [`Decoder::set_multi_stream`] for more details.
+    ///
+    /// [`Decoder::set_multi_stream`]: crate::decoder::Decoder::set_multi_stream
+    pub fn set_multi_stream(&mut self, multi_stream: bool) {
+        self.multi_stream = multi_stream;
+    }
+
+    /// Set the [`Limits`] on the resources used for decoding
+    ///
+    /// Exceeding one of them makes [`ParallelDecoder::read`], or [`ParallelDecoder::write`]
+    /// for limits checked while scheduling blocks, return [`DecoderError::LimitExceeded`].
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Set whether to skip the blocks which can't be decoded,
+    /// instead of returning an error
+    ///
+    /// See [`Decoder::set_skip_corrupt_blocks`] for more details.
+    /// Since blocks are found by scanning the compressed data for their
+    /// magic value, a block whose magic is damaged is only reported through
+    /// the mismatching stream CRC.
+    ///
+    /// [`Decoder::set_skip_corrupt_blocks`]: crate::decoder::Decoder::set_skip_corrupt_blocks
+    pub fn set_skip_corrupt_blocks(&mut self, skip_corrupt_blocks: bool) {
+        self.skip_corrupt_blocks = skip_corrupt_blocks;
+    }
+
+    /// Set the [`BufferPool`] the buffers for decoding blocks are taken from
+    ///
+    /// See [`BufferPool`] for more details.
+    pub fn set_buffer_pool(&mut self, buffer_pool: BufferPool) {
+        self.buffer_pool = buffer_pool;
+    }
+
+    /// The errors of the blocks skipped because of
+    /// [`ParallelDecoder::set_skip_corrupt_blocks`], in order
+    pub fn skipped_errors(&self) -> &[DecoderError] {
+        &self.skipped_errors
+    }
+
+    /// A summary of the data decoded so far
+    pub fn summary(&self) -> &VerifySummary {
+        &self.summary
+    }
 }
 
 impl<P: ThreadPool> ParallelDecoder<P> {
     /// Read decompressed data into `buf`.
     pub fn read(&mut self, buf: &mut [u8]) -> Result<ReadState, DecoderError> {
-        match self.receive_pool.get_mut(&self.receive_index) {
-            Some(Some((pre_read, block))) => {
-                // there's a block here
-
-                let original_len = buf.len();
-                let buf = pre_read.read(buf);
-                let read1 = original_len - buf.len();
+        // the bytes read from the blocks which have already been fully read
+        let mut read = 0;
+
+        loop {
+            self.check_stream_end()?;
+
+            let buf = &mut buf[read..];
+            match self.receive_pool.get_mut(&self.receive_index) {
+                Some(Some(Ok((pre_read, block, location)))) => {
+                    // there's a block here
+
+                    let original_len = buf.len();
+                    let buf = pre_read.read(buf);
+                    let read1 = original_len - buf.len();
+                    self.summary.uncompressed_len += read1 as u64;
+                    self.limits
+                        .check_uncompressed_len(self.summary.uncompressed_len)?;
+
+                    if buf.is_empty() || original_len == 0 {
+                        return Ok(ReadState::Read(read + read1));
+                    }
 
-                if !buf.is_empty() && original_len > 0 {
                     // the pre_read has been exhausted
-                    let read2 = block.read_from_block(buf)?;
-
-                    if read2 == 0 {
-                        // Deallocate this block
-                        let _ = self.receive_pool.remove(&self.receive_index);
-                        // Go to the next block
-                        self.receive_index += 1;
-
-                        let r = self.read(buf)?;
-                        match r {
-                            ReadState::NeedsWrite if read1 == 0 => Ok(ReadState::NeedsWrite),
-                            ReadState::NeedsWrite => Ok(ReadState::Read(read1)),
-                            ReadState::Read(n) => Ok(ReadState::Read(read1 + n)),
-                            ReadState::Eof if read1 == 0 => Ok(ReadState::Eof),
-                            ReadState::Eof => Ok(ReadState::Read(read1)),
+                    let location = *location;
+                    let read2 = match block.read_from_block(buf) {
+                        Ok(read2) => read2,
+                        Err(err) if self.skip_corrupt_blocks => {
+                            self.skip_block(err.with_location(location));
+                            read += read1;
+                            continue;
                         }
-                    } else {
-                        Ok(ReadState::Read(read1 + read2))
+                        Err(err) => return Err(err.with_location(location).into()),
+                    };
+                    self.summary.uncompressed_len += read2 as u64;
+                    self.limits
+                        .check_uncompressed_len(self.summary.uncompressed_len)?;
+
+                    if read2 > 0 {
+                        return Ok(ReadState::Read(read + read1 + read2));
                     }
-                } else {
-                    Ok(ReadState::Read(read1))
-                }
-            }
-            Some(None) => {
-                // this block is already scheduled for decoding
 
-                loop {
-                    let (receive_index, block) = self.receiver.recv().unwrap();
-                    let block = block?;
+                    self.stream_crc = crc::combine(self.stream_crc, block.crc());
+                    self.summary.blocks += 1;
+
+                    // continue with the next block
+                    self.remove_block();
+                    read += read1;
+                }
+                Some(Some(Err(err))) => {
+                    // this block couldn't be decoded and has to be skipped
+                    let err = err.clone();
+                    self.skip_block(err);
+                }
+                Some(None) => {
+                    // this block is already scheduled for decoding
+
+                    loop {
+                        let (receive_index, block) = self.receiver.recv().unwrap();
+                        if !self.skip_corrupt_blocks {
+                            if let Err(err) = block {
+                                return Err(err.into());
+                            }
+                        }
 
-                    self.receive_pool.insert(receive_index, Some(block));
+                        self.receive_pool.insert(receive_index, Some(block));
 
-                    // we finally got the block we were waiting for
-                    if self.receive_index == receive_index {
-                        return self.read(buf);
+                        // we finally got the block we were waiting for
+                        if self.receive_index == receive_index {
+                            break;
+                        }
                     }
                 }
+                None => {
+                    // this block hasn't yet been scheduled for decoding
+
+                    return Ok(if read > 0 {
+                        ReadState::Read(read)
+                    } else if self.eof {
+                        // the eof flag has been set, and no more blocks are in the queue.
+                        // we reached the eof
+                        ReadState::Eof
+                    } else {
+                        // more blocks are available for decoding
+                        ReadState::NeedsWrite
+                    });
+                }
             }
-            None => {
-                // this block hasn't yet been scheduled for decoding
-
-                if self.eof {
-                    // the eof flag has been set, and no more blocks are in the queue.
-                    // we reached the eof
-                    Ok(ReadState::Eof)
-                } else {
-                    // more blocks are available for decoding
-                    Ok(ReadState::NeedsWrite)
+        }
+    }
+
+    /// Record the error of a corrupt block and go to the next one
+    fn skip_block(&mut self, err: BlockError) {
+        self.skipped_errors.push(err.into());
+        self.stream_damaged = true;
+
+        self.remove_block();
+    }
+
+    /// Deallocate the block being read and go to the next one
+    fn remove_block(&mut self) {
+        let _ = self.receive_pool.remove(&self.receive_index);
+        self.receive_index += 1;
+
+        let budget = self.block_budgets.pop_front().unwrap_or(0);
+        self.reserved_memory = self.reserved_memory.saturating_sub(budget);
+    }
+
+    /// Verify the crc of the current stream, if all of its blocks have been read
+    fn check_stream_end(&mut self) -> Result<(), DecoderError> {
+        while let Some(&(end_index, expected, location)) = self.stream_ends.front() {
+            if end_index != self.receive_index {
+                break;
+            }
+
+            self.stream_ends.pop_front();
+
+            let actual = mem::replace(&mut self.stream_crc, 0);
+            let damaged = mem::replace(&mut self.stream_damaged, false);
+            if expected != actual && !damaged && self.verify_crc {
+                let err = DecoderError::BadStreamCrc {
+                    expected,
+                    actual,
+                    location,
+                };
+                if !self.skip_corrupt_blocks {
+                    return Err(err);
                 }
+
+                self.skipped_errors.push(err);
+            }
+            self.summary.stream_crcs.push(expected);
+        }
+
+        Ok(())
+    }
+
+    /// Decode the data written so far, checking its integrity
+    /// but throwing away the decompressed data
+    ///
+    /// See [`Decoder::verify`] for more details.
+    ///
+    /// [`Decoder::verify`]: crate::decoder::Decoder::verify
+    pub fn verify(&mut self) -> Result<ReadState, DecoderError> {
+        let mut buf = [0; 8 * 1024];
+        loop {
+            match self.read(&mut buf)? {
+                ReadState::Read(_) => {}
+                state => return Ok(state),
             }
         }
     }
@@ -229,156 +431,329 @@ impl<P: ThreadPool> ParallelDecoder<P> {
             return if buf.is_empty() {
                 Ok(())
             } else {
-                Err(BlockError::new("eof").into())
+                Err(BlockError::new(BlockErrorKind::WriteAfterEof, "eof").into())
             };
         }
 
-        match self.header.clone() {
-            Some(header) => {
-                if buf.is_empty() {
-                    self.eof = true;
-                } else {
-                    self.in_buf.extend_from_slice(buf);
-                }
+        if buf.is_empty() {
+            self.write_eof = true;
+        } else {
+            self.in_buf.extend_from_slice(buf);
+        }
 
-                let skip_bytes = self.skip_bits / 8;
-                let filled_portion = self.in_buf.len() - skip_bytes;
-                let min_blocks = filled_portion / (header.max_blocksize() as usize);
-
-                if buf.is_empty() || min_blocks >= self.pool.max_threads().get() {
-                    // let's decode the blocks in `self.in_buf`
-
-                    let in_buf = mem::replace(&mut self.in_buf, Vec::new());
-                    let in_buf = Arc::<[u8]>::from(in_buf);
-
-                    let in_buf_ = Arc::clone(&in_buf);
-                    let mut signatures = find_signatures_parallel(in_buf_, &self.pool);
-                    match signatures.pop() {
-                        Some(last_signature) => {
-                            if !buf.is_empty() {
-                                // this isn't the last write, so we have to put the last block back into `self.in_buf`
-                                // because it's still truncated at this stage
-                                self.in_buf
-                                    .extend_from_slice(&in_buf[(last_signature / 8) as usize..]);
-                            } else {
-                                // this is the last write, put the last block back
-                                signatures.push(last_signature);
+        loop {
+            match self.header.clone() {
+                Some(header) => {
+                    if !self.schedule_blocks(header)? {
+                        return Ok(());
+                    }
+                }
+                None => {
+                    debug_assert_eq!(self.skip_bits % 8, 0);
+                    let skip_bytes = self.skip_bits / 8;
+
+                    match self.in_buf.get(skip_bytes..skip_bytes + 4) {
+                        Some(raw_header) => match Header::parse(raw_header.try_into().unwrap()) {
+                            Ok(header) => {
+                                self.limits.check_streams(self.streams + 1)?;
+                                self.header = Some(header);
+                                self.skip_bits += 4 * 8;
                             }
-                            self.skip_bits = (last_signature % 8) as usize;
-
-                            let num_signatures = signatures.len();
-                            for signature_index in signatures {
-                                let max_preread_len = self.max_preread_len / num_signatures;
-                                let sender = self.sender.clone();
-                                let header = header.clone();
-                                let in_buf = Arc::clone(&in_buf);
-
-                                // get a space for writing the decoded block into
-                                let block_index = self.next_index;
-                                self.next_index = self.next_index.wrapping_add(1);
-                                self.receive_pool.insert(block_index, None);
-
-                                // spawn the block decoder
-                                self.pool.spawn(move || {
-                                    let bytes_num = signature_index / 8;
-                                    let bits_num = signature_index % 8;
-
-                                    let mut reader =
-                                        BitReader::new([&in_buf[bytes_num as usize..], &[]]);
-                                    for _ in 0..bits_num {
-                                        reader.next().expect("enough bits");
-                                    }
-
-                                    let mut block = Block::new(header);
-                                    match block.read_block(&mut reader) {
-                                        Ok(b) => {
-                                            if b.is_none() {
-                                                // we reached the EOF
-                                                return;
-                                            }
-
-                                            let mut pre_read = Vec::new();
-
-                                            loop {
-                                                let remaining = max_preread_len - pre_read.len();
-                                                if remaining == 0 {
-                                                    // we reached the maximum pre read len
-                                                    break;
-                                                }
-
-                                                let mut filled = pre_read.len();
-                                                pre_read
-                                                    .resize(filled + remaining.min(32 * 1024), 0);
-                                                match block.read_from_block(&mut pre_read[filled..])
-                                                {
-                                                    Ok(read) => {
-                                                        filled += read;
-
-                                                        // will the next read succeed?
-                                                        let end = filled < pre_read.len();
-
-                                                        // remove the extra zeros
-                                                        pre_read.truncate(filled);
-
-                                                        if end {
-                                                            // end of block
-                                                            break;
-                                                        }
-                                                    }
-                                                    Err(err) => {
-                                                        let _ =
-                                                            sender.send((block_index, Err(err)));
-                                                        break;
-                                                    }
-                                                }
-                                            }
-
-                                            let pre_read = ReadableVec::from(pre_read);
-                                            let _ =
-                                                sender.send((block_index, Ok((pre_read, block))));
-                                        }
-                                        Err(err) => {
-                                            let _ = sender.send((block_index, Err(err)));
-                                        }
-                                    }
-                                });
+                            Err(_) if self.streams > 0 => {
+                                // trailing garbage after the last stream
+                                self.eof = true;
+                                return Ok(());
                             }
+                            Err(err) => return Err(err.into()),
+                        },
+                        None if self.write_eof && self.streams > 0 => {
+                            // no more streams
+                            self.eof = true;
+                            return Ok(());
                         }
-                        None => {
-                            // no signatures where found???
+                        None => return Ok(()),
+                    }
+                }
+            }
+        }
+    }
 
-                            let bytes_num = self.skip_bits / 8;
-                            let bits_num = self.skip_bits % 8;
+    /// Schedule the decoding of the blocks found in `self.in_buf`
+    ///
+    /// Returns `true` if the end of the current stream has been reached
+    /// and the next one should be looked for.
+    fn schedule_blocks(&mut self, header: Header) -> Result<bool, DecoderError> {
+        let skip_bytes = self.skip_bits / 8;
+        let filled_portion = self.in_buf.len() - skip_bytes;
+        let min_blocks = filled_portion / (header.max_blocksize() as usize);
+
+        // don't wait for more blocks if buffering them would take too much memory
+        let max_memory = self.limits.max_buffered_memory;
+        let memory_full = max_memory.map_or(false, |max| self.in_buf.len() >= max / 2);
+
+        if !self.write_eof && !memory_full && min_blocks < self.pool.max_threads().get() {
+            // wait for more data to be written
+            return Ok(false);
+        }
 
-                            let mut reader = BitReader::new([&in_buf[bytes_num..], &[]]);
-                            for _ in 0..bits_num {
-                                reader.next().expect("enough bits");
-                            }
+        // let's decode the blocks in `self.in_buf`
+
+        let in_buf = mem::replace(&mut self.in_buf, Vec::new());
+        let in_buf = Arc::<[u8]>::from(in_buf);
+
+        let in_buf_ = Arc::clone(&in_buf);
+        let mut signatures = find_signatures_parallel(in_buf_, &self.pool);
+        let skip_bits = self.skip_bits as u64;
+        signatures.retain(|&signature| signature >= skip_bits);
+
+        let mut last_signature = if !self.write_eof {
+            // this isn't the last write, so we have to put the last block back into `self.in_buf`
+            // because it's still truncated at this stage
+            signatures.pop()
+        } else {
+            None
+        };
+
+        if signatures.is_empty() && last_signature.is_none() {
+            // no signatures where found???
+
+            let mut reader = BitReader::new([&in_buf[skip_bytes..], &[]]);
+            reader
+                .skip((self.skip_bits % 8) as u32)
+                .expect("enough bits");
+
+            let location = self.location(skip_bits);
+            reader
+                .read_u64(48)
+                .ok_or_else(|| BlockError::truncated(Field::Magic).with_location(location))?;
+            return Err(
+                BlockError::new(BlockErrorKind::NoBlocks, "no blocks have been found")
+                    .with_location(location)
+                    .into(),
+            );
+        }
 
-                            let magic = reader.read_u64(48).ok_or_else(|| {
-                                BlockError::new("no blocks have been found - eof")
-                            })?;
-                            if magic != FINAL_MAGIC {
-                                return Err(BlockError::new("no blocks have been found").into());
-                            }
+        // the blocks share the memory left after the compressed data and the blocks
+        // which are still being decoded or read. They take their buffers from
+        // `buffer_pool`, whose unused buffers are given up if they don't fit in it
+        let mut available_memory = max_memory.map_or(usize::MAX, |max| {
+            let available = max.saturating_sub(in_buf.len() + self.reserved_memory);
+            self.buffer_pool.shrink_to(available);
+            available
+        });
+
+        let mut header = header;
+        // the signatures before this are part of a stream end which has already been handled
+        let mut stream_start = skip_bits;
+
+        let num_signatures = signatures.len();
+        for signature_index in signatures {
+            if signature_index < stream_start {
+                continue;
+            }
+
+            let bytes_num = signature_index / 8;
+            let bits_num = signature_index % 8;
+
+            let mut reader = BitReader::new([&in_buf[bytes_num as usize..], &[]]);
+            reader.skip(bits_num as u32).expect("enough bits");
+
+            if reader.read_u64(48) == Some(FINAL_MAGIC) {
+                // the end of stream marker has been found
+                let location = self.location(signature_index);
+                let stream_crc = reader.read_u32(32).ok_or_else(|| {
+                    BlockError::truncated(Field::StreamCrc).with_location(location)
+                })?;
+                // it gets verified once every block before it has been read
+                self.stream_ends
+                    .push_back((self.next_index, stream_crc, location));
+                self.streams += 1;
+                self.stream_block_index = 0;
+
+                if !self.multi_stream {
+                    self.eof = true;
+                    return Ok(false);
+                }
 
+                // the next stream starts at the next byte boundary after the stream crc
+                let next_stream = ((signature_index + 48 + 32 + 7) / 8) as usize;
+                match in_buf.get(next_stream..next_stream + 4) {
+                    Some(raw_header) => match Header::parse(raw_header.try_into().unwrap()) {
+                        Ok(next_header) => {
+                            // keep scheduling the blocks of the next stream
+                            self.limits.check_streams(self.streams + 1)?;
+                            header = next_header;
+                            stream_start = (next_stream as u64 + 4) * 8;
+                            continue;
+                        }
+                        Err(_) => {
+                            // trailing garbage after the last stream
                             self.eof = true;
+                            return Ok(false);
                         }
+                    },
+                    None => {
+                        // the header of the next stream hasn't been fully written yet
+                        self.in_buf
+                            .extend_from_slice(in_buf.get(next_stream..).unwrap_or_default());
+                        self.in_buf_offset += next_stream as u64 * 8;
+                        self.skip_bits = 0;
+                        self.header = None;
+                        return Ok(true);
                     }
                 }
             }
-            None => {
-                self.in_buf.extend_from_slice(buf);
 
-                if self.in_buf.len() >= 4 {
-                    let header = Header::parse(self.in_buf[..4].try_into().unwrap())?;
-                    self.header = Some(header);
+            self.limits.check_blocks(self.scheduled_blocks + 1)?;
 
-                    self.skip_bits = 4 * 8;
-                }
+            let tt_len = tt_memory(&header);
+            if tt_len > available_memory && !self.receive_pool.is_empty() {
+                // wait for the blocks being decoded to be read before scheduling this one
+                last_signature = Some(signature_index);
+                break;
             }
+
+            let max_preread_len = self
+                .max_preread_len
+                .min(available_memory.saturating_sub(tt_len))
+                / num_signatures;
+            available_memory = available_memory.saturating_sub(tt_len + max_preread_len);
+            self.spawn_block(
+                Arc::clone(&in_buf),
+                signature_index,
+                header.clone(),
+                max_preread_len,
+            );
         }
 
-        Ok(())
+        match last_signature {
+            Some(last_signature) if last_signature >= stream_start => {
+                self.in_buf
+                    .extend_from_slice(&in_buf[(last_signature / 8) as usize..]);
+                self.in_buf_offset += (last_signature / 8) * 8;
+                self.skip_bits = (last_signature % 8) as usize;
+            }
+            Some(_) => {
+                // the last signature found is part of the stream end which has been handled,
+                // the next stream starts right after its header
+                let start = (stream_start / 8) as usize;
+                self.in_buf.extend_from_slice(&in_buf[start..]);
+                self.in_buf_offset += start as u64 * 8;
+                self.skip_bits = 0;
+            }
+            None => {
+                // this was the last write and the stream has been truncated
+                // before the end of stream marker
+                let location = self.location(in_buf.len() as u64 * 8);
+                return Err(BlockError::truncated(Field::Magic)
+                    .with_location(location)
+                    .into());
+            }
+        }
+        self.header = Some(header);
+
+        // the rest of the block has to be buffered before it can be decoded
+        self.limits.check_buffered_memory(self.in_buf.len())?;
+
+        Ok(false)
     }
+
+    /// The location of the next block of the current stream, whose magic
+    /// is `signature_index` bits into `self.in_buf`
+    fn location(&self, signature_index: u64) -> Location {
+        Location {
+            bit_offset: self.in_buf_offset + signature_index,
+            block_index: self.stream_block_index,
+        }
+    }
+
+    fn spawn_block(
+        &mut self,
+        in_buf: Arc<[u8]>,
+        signature_index: u64,
+        header: Header,
+        max_preread_len: usize,
+    ) {
+        let sender = self.sender.clone();
+
+        // get a space for writing the decoded block into
+        let block_index = self.next_index;
+        self.next_index = self.next_index.wrapping_add(1);
+        self.receive_pool.insert(block_index, None);
+        let budget = tt_memory(&header).saturating_add(max_preread_len);
+        self.block_budgets.push_back(budget);
+        self.reserved_memory = self.reserved_memory.saturating_add(budget);
+        self.scheduled_blocks += 1;
+
+        let location = self.location(signature_index);
+        self.stream_block_index += 1;
+        let verify_crc = self.verify_crc;
+        let buffer_pool = self.buffer_pool.clone();
+
+        // spawn the block decoder
+        self.pool.spawn(move || {
+            let bytes_num = signature_index / 8;
+            let bits_num = signature_index % 8;
+
+            let mut reader = BitReader::new([&in_buf[bytes_num as usize..], &[]]);
+            reader.skip(bits_num as u32).expect("enough bits");
+
+            let mut block = Block::with_pool(header, &buffer_pool);
+            block.set_verify_crc(verify_crc);
+            match block
+                .read_block(&mut reader)
+                .map_err(|err| err.with_location(location))
+            {
+                Ok(b) => {
+                    debug_assert!(b.is_some(), "only blocks are scheduled");
+
+                    let mut pre_read = buffer_pool.take_pre_read();
+
+                    loop {
+                        let remaining = max_preread_len - pre_read.len();
+                        if remaining == 0 {
+                            // we reached the maximum pre read len
+                            break;
+                        }
+
+                        let mut filled = pre_read.len();
+                        pre_read.resize(filled + remaining.min(32 * 1024), 0);
+                        match block.read_from_block(&mut pre_read[filled..]) {
+                            Ok(read) => {
+                                filled += read;
+
+                                // will the next read succeed?
+                                let end = filled < pre_read.len();
+
+                                // remove the extra zeros
+                                pre_read.truncate(filled);
+
+                                if end {
+                                    // end of block
+                                    break;
+                                }
+                            }
+                            Err(err) => {
+                                buffer_pool.put_pre_read(pre_read);
+                                let _ =
+                                    sender.send((block_index, Err(err.with_location(location))));
+                                return;
+                            }
+                        }
+                    }
+
+                    let pre_read = ReadableVec::new(pre_read, buffer_pool);
+                    let _ = sender.send((block_index, Ok((pre_read, block, location))));
+                }
+                Err(err) => {
+                    let _ = sender.send((block_index, Err(err)));
+                }
+            }
+        });
+    }
+}
+
+/// The memory taken by the `tt` buffer of a block of a stream with `header`
+fn tt_memory(header: &Header) -> usize {
+    header.max_blocksize() as usize * mem::size_of::<u32>()
 }
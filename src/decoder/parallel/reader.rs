@@ -1,10 +1,8 @@
-#[cfg(feature = "nightly")]
-use std::io::BorrowedBuf;
 use std::io::{self, Read, Result};
-#[cfg(feature = "nightly")]
-use std::mem::MaybeUninit;
 
-use super::{ParallelDecoder, ReadState, ThreadPool};
+use super::{
+    BufferPool, DecoderError, DecoderOptions, Limits, ParallelDecoder, ReadState, ThreadPool,
+};
 
 /// A high-level **multi-threaded** decoder that wraps a [`Read`] and implements [`Read`], yielding decompressed bytes
 ///
@@ -57,6 +55,8 @@ pub struct ParallelDecoderReader<R, P> {
     decoder: ParallelDecoder<P>,
 
     reader: R,
+    // space for reading compressed data from `reader` into
+    in_buf: Box<[u8]>,
 }
 
 impl<R, P> ParallelDecoderReader<R, P> {
@@ -83,38 +83,95 @@ impl<R, P> ParallelDecoderReader<R, P> {
     /// [`DecoderReader`]: crate::DecoderReader
     /// [`RayonThreadPool`]: crate::RayonThreadPool
     pub fn new(reader: R, pool: P, max_preread_len: usize) -> Self {
+        Self::with_options(
+            reader,
+            pool,
+            DecoderOptions::new().max_preread_len(max_preread_len),
+        )
+    }
+
+    /// Construct a new decoder from something implementing [`Read`],
+    /// configured through `options`
+    ///
+    /// See [`ParallelDecoderReader::new`] for more details.
+    pub fn with_options(reader: R, pool: P, options: DecoderOptions) -> Self {
         Self {
-            decoder: ParallelDecoder::new(pool, max_preread_len),
+            decoder: ParallelDecoder::with_options(pool, options),
 
             reader,
+            in_buf: vec![0; options.input_buf_len].into_boxed_slice(),
         }
     }
+
+    /// Set whether to continue decoding into the next stream once the
+    /// end of the current one is reached
+    ///
+    /// See [`Decoder::set_multi_stream`] for more details.
+    ///
+    /// [`Decoder::set_multi_stream`]: crate::decoder::Decoder::set_multi_stream
+    pub fn set_multi_stream(&mut self, multi_stream: bool) {
+        self.decoder.set_multi_stream(multi_stream);
+    }
+
+    /// Set the [`Limits`] on the resources used for decoding
+    ///
+    /// See [`ParallelDecoder::set_limits`] for more details.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.decoder.set_limits(limits);
+    }
+
+    /// Set whether to skip the blocks which can't be decoded,
+    /// instead of returning an error
+    ///
+    /// See [`ParallelDecoder::set_skip_corrupt_blocks`] for more details.
+    pub fn set_skip_corrupt_blocks(&mut self, skip_corrupt_blocks: bool) {
+        self.decoder.set_skip_corrupt_blocks(skip_corrupt_blocks);
+    }
+
+    /// Set the [`BufferPool`] the buffers for decoding blocks are taken from
+    ///
+    /// See [`ParallelDecoder::set_buffer_pool`] for more details.
+    pub fn set_buffer_pool(&mut self, buffer_pool: BufferPool) {
+        self.decoder.set_buffer_pool(buffer_pool);
+    }
+
+    /// The errors of the blocks skipped because of
+    /// [`ParallelDecoderReader::set_skip_corrupt_blocks`], in order
+    pub fn skipped_errors(&self) -> &[DecoderError] {
+        self.decoder.skipped_errors()
+    }
+
+    /// Get a reference to the underlying reader
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Get a mutable reference to the underlying reader
+    ///
+    /// Reading from it may corrupt the output of this decoder,
+    /// since data could be buffered in it.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Consume this decoder, returning the underlying reader
+    ///
+    /// Any data which has already been read from the underlying
+    /// reader, but not yet decoded, is lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
 }
 
 impl<R: Read, P: ThreadPool> Read for ParallelDecoderReader<R, P> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         let mut read_zero = false;
-        #[cfg(not(feature = "nightly"))]
-        let mut tmp_buf = [0; 1024];
-        #[cfg(feature = "nightly")]
-        let mut tmp_buf = [MaybeUninit::uninit(); 1024];
-        #[cfg(feature = "nightly")]
-        let mut read_buf = BorrowedBuf::from(tmp_buf.as_mut_slice());
 
         loop {
             match self.decoder.read(buf)? {
                 ReadState::NeedsWrite => {
-                    #[cfg(feature = "nightly")]
-                    let read = {
-                        read_buf.clear();
-                        self.reader.read_buf(read_buf.unfilled())?;
-                        read_buf.filled()
-                    };
-                    #[cfg(not(feature = "nightly"))]
-                    let read = {
-                        let n = self.reader.read(&mut tmp_buf)?;
-                        &tmp_buf[..n]
-                    };
+                    let n = self.reader.read(&mut self.in_buf)?;
+                    let read = &self.in_buf[..n];
 
                     if read_zero && self.decoder.header.is_none() {
                         return Err(io::Error::new(
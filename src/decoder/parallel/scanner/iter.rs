@@ -1,6 +1,6 @@
 use super::linear::find_next_signature;
 
-/// An iterator returning the bit offsets into `buf` to `BLOCK_MAGIC`
+/// An iterator returning the bit offsets into `buf` to `BLOCK_MAGIC` and `FINAL_MAGIC`
 pub struct SignatureFinder<'a> {
     buf: &'a [u8],
     skip: u64,
@@ -56,9 +56,7 @@ mod tests {
                 let pos = finder.next().unwrap();
 
                 let mut reader = BitReader::new([&repeated_haystack, &[]]);
-                for _ in 0..pos {
-                    reader.next().expect("enough bits");
-                }
+                reader.skip(pos as u32).expect("enough bits");
 
                 let magic = reader.read_u64(48).unwrap();
                 assert_eq!(BLOCK_MAGIC, magic);
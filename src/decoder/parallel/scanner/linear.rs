@@ -1,8 +1,9 @@
 use std::convert::TryInto;
 
-use crate::decoder::block::BLOCK_MAGIC;
+use crate::decoder::block::{BLOCK_MAGIC, FINAL_MAGIC};
 
-/// Returns the bit offset into `buf` to `BLOCK_MAGIC`, or `None` if it's not found
+/// Returns the bit offset into `buf` to either `BLOCK_MAGIC` or `FINAL_MAGIC`,
+/// or `None` if neither of them is found
 pub fn find_next_signature(buf: &[u8]) -> Option<u64> {
     for (byte_index, window) in buf.windows(8).enumerate().step_by(2) {
         let window = u64::from_be_bytes(window.try_into().expect("unreachable"));
@@ -11,7 +12,7 @@ pub fn find_next_signature(buf: &[u8]) -> Option<u64> {
             ($shift: expr) => {
                 let window = window >> $shift;
                 let signature = window & !(u64::MAX << 48);
-                if signature == BLOCK_MAGIC {
+                if signature == BLOCK_MAGIC || signature == FINAL_MAGIC {
                     return Some((byte_index as u64) * 8 + (16 - $shift));
                 }
             };
@@ -33,6 +34,7 @@ pub fn find_next_signature(buf: &[u8]) -> Option<u64> {
         unrolled_check!(13);
         unrolled_check!(14);
         unrolled_check!(15);
+        unrolled_check!(16);
     }
 
     None
@@ -59,9 +61,7 @@ mod tests {
             assert_eq!(pos, (1024 * 8) + (128 - 48 - shift));
 
             let mut reader = BitReader::new([&haystack, &[]]);
-            for _ in 0..pos {
-                reader.next().expect("enough bits");
-            }
+            reader.skip(pos as u32).expect("enough bits");
 
             let magic = reader.read_u64(48).unwrap();
             assert_eq!(BLOCK_MAGIC, magic);
@@ -84,12 +84,30 @@ mod tests {
             assert_eq!(pos, (1024 * 8) + (128 - 48 - shift));
 
             let mut reader = BitReader::new([&haystack, &[]]);
-            for _ in 0..pos {
-                reader.next().expect("enough bits");
-            }
+            reader.skip(pos as u32).expect("enough bits");
 
             let magic = reader.read_u64(48).unwrap();
             assert_eq!(BLOCK_MAGIC, magic);
         }
     }
+
+    #[test]
+    fn find_at_start() {
+        let mut haystack = u64::to_be_bytes(BLOCK_MAGIC << 16).to_vec();
+        haystack.resize(1024, 0);
+
+        assert_eq!(find_next_signature(&haystack), Some(0));
+    }
+
+    #[test]
+    fn find_final_magic() {
+        for shift in 0..=80 {
+            let mut haystack = vec![0u8; 1024];
+            let shifted = u128::from(FINAL_MAGIC) << shift;
+            haystack.extend_from_slice(&u128::to_be_bytes(shifted));
+
+            let pos = find_next_signature(&haystack).unwrap();
+            assert_eq!(pos, (1024 * 8) + (128 - 48 - shift));
+        }
+    }
 }
@@ -1,5 +1,5 @@
 //! Scanner for finding the block magic of all blocks in a bitstream
 
-mod iter;
+pub mod iter;
 mod linear;
 pub mod threaded;
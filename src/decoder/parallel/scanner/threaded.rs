@@ -4,7 +4,7 @@ use std::sync::Arc;
 use super::iter::SignatureFinder;
 use crate::ThreadPool;
 
-/// An iterator returning the bit offsets into `buf` to `BLOCK_MAGIC`
+/// Returns the sorted bit offsets into `memory` to `BLOCK_MAGIC` and `FINAL_MAGIC`
 pub fn find_signatures_parallel<P>(memory: Arc<[u8]>, pool: &P) -> Vec<u64>
 where
     P: ThreadPool,
@@ -34,6 +34,8 @@ where
 
     let mut indexes = receiver.into_iter().collect::<Vec<u64>>();
     indexes.sort_unstable();
+    // signatures at the boundary between two chunks are found by both of them
+    indexes.dedup();
 
     indexes
 }
@@ -85,9 +87,7 @@ mod tests {
                 let pos = finder.next().unwrap();
 
                 let mut reader = BitReader::new([&repeated_haystack, &[]]);
-                for _ in 0..pos {
-                    reader.next().expect("enough bits");
-                }
+                reader.skip(pos as u32).expect("enough bits");
 
                 let magic = reader.read_u64(48).unwrap();
                 assert_eq!(BLOCK_MAGIC, magic);
@@ -96,4 +96,14 @@ mod tests {
             assert!(finder.next().is_none());
         }
     }
+
+    #[test]
+    fn find_at_chunk_boundary() {
+        let mut haystack = vec![0u8; 4 * 1024];
+        haystack[1024..1032].copy_from_slice(&u64::to_be_bytes(BLOCK_MAGIC << 16));
+        let haystack = Arc::<[u8]>::from(haystack);
+
+        let signatures = find_signatures_parallel(haystack, &NaiveThreadPool);
+        assert_eq!(signatures, [1024 * 8]);
+    }
 }
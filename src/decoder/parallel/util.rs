@@ -1,10 +1,19 @@
-/// A `Vec<u8>` which can be `read`
+use std::mem;
+
+use crate::decoder::BufferPool;
+
+/// A `Vec<u8>` which can be `read`, given back to a [`BufferPool`] on drop
 pub struct ReadableVec {
     vec: Vec<u8>,
     skip: usize,
+    pool: BufferPool,
 }
 
 impl ReadableVec {
+    pub fn new(vec: Vec<u8>, pool: BufferPool) -> Self {
+        Self { vec, skip: 0, pool }
+    }
+
     /// Try to fill `buf` and return the unfilled portion of it
     pub fn read<'a>(&mut self, buf: &'a mut [u8]) -> &'a mut [u8] {
         let available = &self.vec[self.skip..];
@@ -16,8 +25,9 @@ impl ReadableVec {
     }
 }
 
-impl From<Vec<u8>> for ReadableVec {
-    fn from(vec: Vec<u8>) -> Self {
-        Self { vec, skip: 0 }
+impl Drop for ReadableVec {
+    fn drop(&mut self) {
+        self.pool
+            .put_pre_read(mem::replace(&mut self.vec, Vec::new()));
     }
 }
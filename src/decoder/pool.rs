@@ -0,0 +1,220 @@
+use std::fmt::{self, Debug, Formatter};
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+/// A pool of the buffers used for decoding blocks
+///
+/// Decoding a block requires a buffer of up to 3.6 MB, plus the buffer
+/// holding the data pre-read by [`ParallelDecoder`]. Instead of allocating
+/// them for every block, decoders take them from their [`BufferPool`]
+/// and give them back once the block has been read, so that they can be
+/// reused by the next block.
+///
+/// Every decoder has its own pool by default. Cloning a [`BufferPool`]
+/// returns a handle to the same pool, which can be shared between any number
+/// of decoders, even running concurrently on different threads, through
+/// `set_buffer_pool`. The pool holds onto at most as many buffers as have
+/// been in use at the same time, up to the limit set through
+/// [`BufferPool::with_max_buffers`], until [`BufferPool::clear`] is called or
+/// every handle to it is dropped. The buffers held by the pool count towards
+/// [`Limits::max_buffered_memory`] in [`ParallelDecoder`], which gives some of
+/// them up in order to respect it.
+///
+/// ```rust
+/// use bzip2_rs::decoder::BufferPool;
+/// use bzip2_rs::DecoderReader;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let pool = BufferPool::new();
+///
+/// for compressed_file in [
+///     include_bytes!("../../tests/samplefiles/sample1.bz2").as_ref(),
+///     include_bytes!("../../tests/samplefiles/sample2.bz2").as_ref(),
+/// ] {
+///     let mut reader = DecoderReader::new(compressed_file);
+///     reader.set_buffer_pool(pool.clone());
+///     std::io::copy(&mut reader, &mut std::io::sink())?;
+/// }
+/// #
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`ParallelDecoder`]: crate::decoder::ParallelDecoder
+/// [`Limits::max_buffered_memory`]: crate::decoder::Limits::max_buffered_memory
+#[derive(Clone)]
+pub struct BufferPool {
+    buffers: Arc<Mutex<Buffers>>,
+}
+
+struct Buffers {
+    tt: Vec<Vec<u32>>,
+    pre_read: Vec<Vec<u8>>,
+    // the maximum number of buffers of each kind held by the pool
+    max_buffers: usize,
+}
+
+impl BufferPool {
+    /// Construct a new empty [`BufferPool`]
+    ///
+    /// It holds onto at most as many buffers of each kind as the
+    /// available parallelism, which is enough for that many decoders
+    /// running at the same time.
+    pub fn new() -> Self {
+        let max_buffers = thread::available_parallelism().map_or(1, |n| n.get());
+        Self::with_max_buffers(max_buffers)
+    }
+
+    /// Construct a new empty [`BufferPool`], holding onto at most
+    /// `max_buffers` unused buffers of each kind
+    ///
+    /// Buffers given back while the pool is full are deallocated.
+    pub fn with_max_buffers(max_buffers: usize) -> Self {
+        let buffers = Buffers {
+            tt: Vec::new(),
+            pre_read: Vec::new(),
+            max_buffers,
+        };
+
+        Self {
+            buffers: Arc::new(Mutex::new(buffers)),
+        }
+    }
+
+    /// Deallocate the buffers which aren't currently being used
+    pub fn clear(&self) {
+        let mut buffers = self.lock();
+        buffers.tt = Vec::new();
+        buffers.pre_read = Vec::new();
+    }
+
+    /// Take an empty `tt` buffer, allocated with a capacity of at least `capacity`
+    pub(crate) fn take_tt(&self, capacity: usize) -> Vec<u32> {
+        let mut tt = self.lock().tt.pop().unwrap_or_default();
+        tt.reserve(capacity);
+        tt
+    }
+
+    /// Give back a `tt` buffer previously taken through [`BufferPool::take_tt`]
+    pub(crate) fn put_tt(&self, mut tt: Vec<u32>) {
+        let mut buffers = self.lock();
+        if buffers.tt.len() < buffers.max_buffers {
+            tt.clear();
+            buffers.tt.push(tt);
+        }
+    }
+
+    /// Take an empty pre-read buffer
+    pub(crate) fn take_pre_read(&self) -> Vec<u8> {
+        self.lock().pre_read.pop().unwrap_or_default()
+    }
+
+    /// Give back a pre-read buffer previously taken through [`BufferPool::take_pre_read`]
+    pub(crate) fn put_pre_read(&self, mut pre_read: Vec<u8>) {
+        let mut buffers = self.lock();
+        if buffers.pre_read.len() < buffers.max_buffers {
+            pre_read.clear();
+            buffers.pre_read.push(pre_read);
+        }
+    }
+
+    /// Deallocate unused buffers until they take at most `max_bytes`,
+    /// returning how many bytes the remaining ones take
+    pub(crate) fn shrink_to(&self, max_bytes: usize) -> usize {
+        let mut buffers = self.lock();
+
+        let mut tt_bytes = buffers
+            .tt
+            .iter()
+            .map(|tt| tt.capacity() * mem::size_of::<u32>())
+            .sum::<usize>();
+        let mut pre_read_bytes = buffers.pre_read.iter().map(Vec::capacity).sum::<usize>();
+
+        // the biggest buffers go first
+        while tt_bytes + pre_read_bytes > max_bytes {
+            if let Some(tt) = buffers.tt.pop() {
+                tt_bytes -= tt.capacity() * mem::size_of::<u32>();
+            } else if let Some(pre_read) = buffers.pre_read.pop() {
+                pre_read_bytes -= pre_read.capacity();
+            } else {
+                break;
+            }
+        }
+
+        tt_bytes + pre_read_bytes
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Buffers> {
+        // the buffers are always left in a consistent state
+        self.buffers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Debug for BufferPool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let buffers = self.lock();
+        f.debug_struct("BufferPool")
+            .field("tt", &buffers.tt.len())
+            .field("pre_read", &buffers.pre_read.len())
+            .field("max_buffers", &buffers.max_buffers)
+            .finish()
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuse() {
+        let pool = BufferPool::new();
+
+        let mut tt = pool.take_tt(1024);
+        assert!(tt.capacity() >= 1024);
+        tt.extend_from_slice(&[1, 2, 3]);
+        let ptr = tt.as_ptr();
+        pool.clone().put_tt(tt);
+
+        let tt = pool.take_tt(16);
+        assert!(tt.is_empty());
+        assert_eq!(tt.as_ptr(), ptr);
+        assert!(pool.take_tt(16).capacity() < 1024);
+
+        pool.put_pre_read(vec![0; 10]);
+        pool.clear();
+        assert_eq!(pool.take_pre_read().capacity(), 0);
+    }
+
+    #[test]
+    fn max_buffers() {
+        let pool = BufferPool::with_max_buffers(2);
+
+        for _ in 0..3 {
+            pool.put_tt(Vec::with_capacity(16));
+            pool.put_pre_read(Vec::with_capacity(16));
+        }
+        assert_eq!(pool.lock().tt.len(), 2);
+        assert_eq!(pool.lock().pre_read.len(), 2);
+    }
+
+    #[test]
+    fn shrink_to() {
+        let pool = BufferPool::new();
+        pool.put_tt(Vec::with_capacity(100));
+        pool.put_pre_read(Vec::with_capacity(100));
+
+        assert_eq!(pool.shrink_to(usize::MAX), 500);
+        // the tt buffer goes first
+        assert_eq!(pool.shrink_to(499), 100);
+        assert_eq!(pool.take_tt(0).capacity(), 0);
+        assert_eq!(pool.shrink_to(0), 0);
+        assert_eq!(pool.take_pre_read().capacity(), 0);
+    }
+}
@@ -1,10 +1,7 @@
-#[cfg(feature = "nightly")]
-use std::io::BorrowedBuf;
 use std::io::{self, Read, Result};
-#[cfg(feature = "nightly")]
-use std::mem::MaybeUninit;
+use std::mem;
 
-use super::{Decoder, ReadState};
+use super::{BufferPool, Decoder, DecoderError, DecoderOptions, Limits, ReadState};
 
 /// A high-level **single-threaded** decoder that wraps a [`Read`] and implements [`Read`], yielding decompressed bytes
 ///
@@ -35,44 +32,112 @@ pub struct DecoderReader<R> {
     decoder: Decoder,
 
     reader: R,
+    // space for reading compressed data from `reader` into
+    in_buf: Box<[u8]>,
 }
 
 impl<R> DecoderReader<R> {
     /// Construct a new decoder from something implementing [`Read`]
     pub fn new(reader: R) -> Self {
+        Self::with_options(reader, DecoderOptions::new())
+    }
+
+    /// Construct a new decoder from something implementing [`Read`],
+    /// configured through `options`
+    pub fn with_options(reader: R, options: DecoderOptions) -> Self {
         Self {
-            decoder: Decoder::new(),
+            decoder: Decoder::with_options(options),
 
             reader,
+            in_buf: vec![0; options.input_buf_len].into_boxed_slice(),
         }
     }
+
+    /// Set whether to continue decoding into the next stream once the
+    /// end of the current one is reached
+    ///
+    /// See [`Decoder::set_multi_stream`] for more details.
+    pub fn set_multi_stream(&mut self, multi_stream: bool) {
+        self.decoder.set_multi_stream(multi_stream);
+    }
+
+    /// Set the [`Limits`] on the resources used for decoding
+    ///
+    /// See [`Decoder::set_limits`] for more details.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.decoder.set_limits(limits);
+    }
+
+    /// Set whether to skip the blocks which can't be decoded,
+    /// instead of returning an error
+    ///
+    /// See [`Decoder::set_skip_corrupt_blocks`] for more details.
+    pub fn set_skip_corrupt_blocks(&mut self, skip_corrupt_blocks: bool) {
+        self.decoder.set_skip_corrupt_blocks(skip_corrupt_blocks);
+    }
+
+    /// Set the [`BufferPool`] the buffers for decoding blocks are taken from
+    ///
+    /// See [`Decoder::set_buffer_pool`] for more details.
+    pub fn set_buffer_pool(&mut self, buffer_pool: BufferPool) {
+        self.decoder.set_buffer_pool(buffer_pool);
+    }
+
+    /// The errors of the blocks skipped because of
+    /// [`DecoderReader::set_skip_corrupt_blocks`], in order
+    pub fn skipped_errors(&self) -> &[DecoderError] {
+        self.decoder.skipped_errors()
+    }
+
+    /// The data read from the underlying reader which hasn't been decoded yet
+    ///
+    /// See [`Decoder::remaining_input`] for more details.
+    pub fn remaining_input(&mut self) -> &[u8] {
+        self.decoder.remaining_input()
+    }
+
+    /// Reset this decoder, making it ready to decompress a new bzip2 file
+    /// read from `reader`
+    ///
+    /// Returns the reader which was previously being read from.
+    /// See [`Decoder::reset`] for more details.
+    pub fn reset(&mut self, reader: R) -> R {
+        self.decoder.reset();
+        mem::replace(&mut self.reader, reader)
+    }
+
+    /// Get a reference to the underlying reader
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Get a mutable reference to the underlying reader
+    ///
+    /// Reading from it may corrupt the output of this decoder,
+    /// since data could be buffered in it.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Consume this decoder, returning the underlying reader
+    ///
+    /// Any data which has already been read from the underlying
+    /// reader, but not yet decoded, is lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
 }
 
 impl<R: Read> Read for DecoderReader<R> {
     /// Decompress bzip2 data from the underlying reader
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         let mut read_zero = false;
-        #[cfg(not(feature = "nightly"))]
-        let mut tmp_buf = [0; 1024];
-        #[cfg(feature = "nightly")]
-        let mut tmp_buf = [MaybeUninit::uninit(); 1024];
-        #[cfg(feature = "nightly")]
-        let mut read_buf = BorrowedBuf::from(tmp_buf.as_mut_slice());
 
         loop {
             match self.decoder.read(buf)? {
                 ReadState::NeedsWrite => {
-                    #[cfg(feature = "nightly")]
-                    let read = {
-                        read_buf.clear();
-                        self.reader.read_buf(read_buf.unfilled())?;
-                        read_buf.filled()
-                    };
-                    #[cfg(not(feature = "nightly"))]
-                    let read = {
-                        let n = self.reader.read(&mut tmp_buf)?;
-                        &tmp_buf[..n]
-                    };
+                    let n = self.reader.read(&mut self.in_buf)?;
+                    let read = &self.in_buf[..n];
 
                     if read_zero && self.decoder.header_block.is_none() {
                         return Err(io::Error::new(
@@ -0,0 +1,166 @@
+use std::io::{self, Write};
+
+use super::block::{Block, FINAL_MAGIC};
+use super::parallel::scanner::iter::SignatureFinder;
+use crate::bitreader::BitReader;
+use crate::bitwriter::BitWriter;
+use crate::header::Header;
+
+/// Recover the intact blocks of a damaged bzip2 file, like `bzip2recover` does
+///
+/// `compressed` is scanned for block signatures. Every block that's found
+/// is decoded independently of the others, and the ones matching their CRC
+/// are returned. Damaged blocks, as well as the false positives of the
+/// signature scanner, are skipped.
+///
+/// The header of the file isn't needed, so this works even if the start of
+/// the file is missing. Blocks are decoded with the biggest blocksize.
+///
+/// ```rust
+/// use bzip2_rs::decoder::recover_blocks;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut compressed_file = include_bytes!("../../tests/samplefiles/sample2.bz2").to_vec();
+/// // damage the first block
+/// compressed_file[1000] ^= 0xff;
+///
+/// let blocks = recover_blocks(&compressed_file).collect::<Vec<_>>();
+/// assert_eq!(blocks.len(), 1);
+///
+/// // the second block can still be decompressed
+/// let mut recovered_file = Vec::new();
+/// blocks[0].write_stream(&mut recovered_file)?;
+/// #
+/// # let expected = std::fs::read("tests/samplefiles/sample2.ref")?;
+/// # assert!(expected.ends_with(blocks[0].data()));
+/// #
+/// # Ok(())
+/// # }
+/// ```
+pub fn recover_blocks(compressed: &[u8]) -> RecoverBlocks<'_> {
+    RecoverBlocks {
+        compressed,
+        signatures: SignatureFinder::new(compressed),
+        header: Header::from_raw_blocksize(9).expect("9 is a valid blocksize"),
+    }
+}
+
+/// An iterator over the intact blocks of a damaged bzip2 file
+///
+/// This `struct` is created by [`recover_blocks`].
+pub struct RecoverBlocks<'a> {
+    compressed: &'a [u8],
+    signatures: SignatureFinder<'a>,
+    header: Header,
+}
+
+/// A block recovered by [`recover_blocks`]
+pub struct RecoveredBlock<'a> {
+    compressed: &'a [u8],
+    bit_offset: u64,
+    bit_len: u64,
+    crc: u32,
+    data: Vec<u8>,
+}
+
+impl<'a> RecoverBlocks<'a> {
+    /// Decode the block whose magic is at `bit_offset`, if it's intact
+    fn recover(&self, bit_offset: u64) -> Option<RecoveredBlock<'a>> {
+        let mut reader = BitReader::new([&self.compressed[(bit_offset / 8) as usize..], &[]]);
+        reader.skip((bit_offset % 8) as u32).expect("enough bits");
+
+        let mut block = Block::new(self.header.clone());
+        // `None` for end of stream markers
+        block.read_block(&mut reader).ok()??;
+        let bit_len = u64::from(reader.position()) - bit_offset % 8;
+
+        let mut data = Vec::new();
+        loop {
+            let filled = data.len();
+            data.resize(filled + 64 * 1024, 0);
+
+            // the crc is verified once the whole block has been read
+            let read = block.read_from_block(&mut data[filled..]).ok()?;
+            data.truncate(filled + read);
+            if read == 0 {
+                break;
+            }
+        }
+
+        Some(RecoveredBlock {
+            compressed: self.compressed,
+            bit_offset,
+            bit_len,
+            crc: block.crc(),
+            data,
+        })
+    }
+}
+
+impl<'a> Iterator for RecoverBlocks<'a> {
+    type Item = RecoveredBlock<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let signature = self.signatures.next()?;
+            if let Some(block) = self.recover(signature) {
+                return Some(block);
+            }
+        }
+    }
+}
+
+impl RecoveredBlock<'_> {
+    /// The offset, in bits from the start of the damaged file, of the magic of this block
+    pub fn bit_offset(&self) -> u64 {
+        self.bit_offset
+    }
+
+    /// The size, in bits, of the compressed block, including its magic
+    pub fn compressed_bit_len(&self) -> u64 {
+        self.bit_len
+    }
+
+    /// The CRC of the decompressed content of this block
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+
+    /// The decompressed content of this block
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Consume this block, returning its decompressed content
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Write this block into `writer` as a standalone bzip2 stream
+    ///
+    /// The compressed block is copied as is, without decompressing and
+    /// compressing it again, in between a `BZh9` header and an end of
+    /// stream marker.
+    pub fn write_stream<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut bits = BitWriter::new();
+        bits.write_bits(32, u32::from_be_bytes(*b"BZh9"));
+
+        let mut reader = BitReader::new([&self.compressed[(self.bit_offset / 8) as usize..], &[]]);
+        reader
+            .skip((self.bit_offset % 8) as u32)
+            .expect("enough bits");
+
+        for _ in 0..self.bit_len / 32 {
+            bits.write_bits(32, reader.read_u32(32).expect("enough bits"));
+        }
+        let remaining = (self.bit_len % 32) as u8;
+        bits.write_bits(remaining, reader.read_u32(remaining).expect("enough bits"));
+
+        // the stream crc of a stream made of a single block is the crc of the block
+        bits.write_u64(48, FINAL_MAGIC);
+        bits.write_bits(32, self.crc);
+        bits.align();
+
+        writer.write_all(bits.bytes())
+    }
+}
@@ -0,0 +1,219 @@
+use std::io::{self, Read, Result, Seek, SeekFrom};
+
+use super::block::{max_compressed_size, max_uncompressed_size, Block, BlockError, BlockErrorKind};
+use super::{BlockIndex, DecoderError};
+use crate::bitreader::BitReader;
+
+/// A high-level **single-threaded** decoder that wraps a [`Read`] + [`Seek`] and implements [`Read`] and [`Seek`], yielding decompressed bytes
+///
+/// Seeking only requires decoding the block containing the new position,
+/// which is found through a [`BlockIndex`].
+///
+/// ```rust
+/// use std::fs::File;
+/// use std::io::{Read, Seek, SeekFrom};
+///
+/// use bzip2_rs::decoder::SeekableDecoderReader;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let compressed_file = File::open("tests/samplefiles/sample2.bz2")?;
+///
+/// // this decodes the whole file once, in order to build the block index
+/// let mut reader = SeekableDecoderReader::new(compressed_file)?;
+///
+/// reader.seek(SeekFrom::Start(150_000))?;
+/// let mut buf = [0; 1024];
+/// reader.read_exact(&mut buf)?;
+/// #
+/// # let expected = std::fs::read("tests/samplefiles/sample2.ref")?;
+/// # assert_eq!(&expected[150_000..150_000 + 1024], buf.as_ref());
+/// #
+/// # Ok(())
+/// # }
+/// ```
+pub struct SeekableDecoderReader<R> {
+    index: BlockIndex,
+    // the position in the decompressed file
+    position: u64,
+
+    // the index and the decompressed content of the last decoded block
+    cached: Option<(usize, Vec<u8>)>,
+    compressed: Vec<u8>,
+    // reused for decoding every block
+    block: Block,
+
+    reader: R,
+}
+
+impl<R: Read + Seek> SeekableDecoderReader<R> {
+    /// Construct a new seekable decoder from something implementing [`Read`] and [`Seek`]
+    ///
+    /// The whole file is decoded, from the start, in order to build its
+    /// [`BlockIndex`]. Use [`SeekableDecoderReader::with_index`] in order
+    /// to reuse an existing index.
+    pub fn new(mut reader: R) -> Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+        let index = BlockIndex::build(&mut reader)?;
+
+        Ok(Self::with_index(reader, index))
+    }
+}
+
+impl<R> SeekableDecoderReader<R> {
+    /// Construct a new seekable decoder from something implementing [`Read`]
+    /// and [`Seek`] and the [`BlockIndex`] of the file it contains
+    ///
+    /// The index can be one previously serialized through [`BlockIndex::write_to`]
+    /// and read back through [`BlockIndex::read_from`]. Reading a block whose CRC
+    /// doesn't match the one in the index returns an error of kind
+    /// [`io::ErrorKind::InvalidData`], since the index was built from a different file.
+    pub fn with_index(reader: R, index: BlockIndex) -> Self {
+        let block = Block::new(index.header().clone());
+
+        Self {
+            index,
+            position: 0,
+
+            cached: None,
+            compressed: Vec::new(),
+            block,
+
+            reader,
+        }
+    }
+
+    /// The [`BlockIndex`] of the file being decoded
+    pub fn index(&self) -> &BlockIndex {
+        &self.index
+    }
+}
+
+impl<R: Read + Seek> SeekableDecoderReader<R> {
+    /// Decode the block at `block_index` in the index into `self.cached`
+    fn load_block(&mut self, block_index: usize) -> Result<()> {
+        let blocks = self.index.blocks();
+        let block = blocks[block_index];
+        if block.uncompressed_len() > max_uncompressed_size(self.index.header()) {
+            // don't allocate more than any block can decode to
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the block index doesn't match the file",
+            ));
+        }
+
+        // read all of the compressed data of the block
+        let start = block.compressed_bit_offset() / 8;
+        let end = match blocks.get(block_index + 1) {
+            Some(next) => (next.compressed_bit_offset() + 7) / 8,
+            None => start + max_compressed_size(self.index.header()) as u64 + 1,
+        };
+
+        self.reader.seek(SeekFrom::Start(start))?;
+        self.compressed.clear();
+        (&mut self.reader)
+            .take(end - start)
+            .read_to_end(&mut self.compressed)?;
+
+        let mut reader = BitReader::new([&self.compressed, &[]]);
+        reader
+            .skip((block.compressed_bit_offset() % 8) as u32)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "block truncated"))?;
+
+        let (_, mut decompressed) = self.cached.take().unwrap_or_default();
+        decompressed.resize(block.uncompressed_len() as usize + 1, 0);
+
+        let read = decode_block(&mut self.block, &mut reader, &mut decompressed)
+            .map_err(DecoderError::from)?;
+        if self.block.crc() != block.crc() || read != block.uncompressed_len() as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the block index doesn't match the file",
+            ));
+        }
+
+        decompressed.truncate(read);
+        self.cached = Some((block_index, decompressed));
+        Ok(())
+    }
+}
+
+/// Decode the block read by `reader` into `out`, returning its size
+///
+/// The CRC of the block is verified, unless it doesn't fit into `out`.
+fn decode_block(
+    block: &mut Block,
+    reader: &mut BitReader<'_>,
+    out: &mut [u8],
+) -> std::result::Result<usize, BlockError> {
+    if block.read_block(reader)?.is_none() {
+        // the end of stream marker has been found instead of a block
+        return Err(BlockError::new(
+            BlockErrorKind::BadMagic,
+            "bad magic value found",
+        ));
+    }
+
+    let mut read = 0;
+    loop {
+        match block.read_from_block(&mut out[read..])? {
+            0 => return Ok(read),
+            n => read += n,
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableDecoderReader<R> {
+    /// Decompress bzip2 data from the underlying reader, starting at the current position
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let block_index = match self.index.find(self.position) {
+            Some(block_index) => block_index,
+            None => return Ok(0),
+        };
+
+        if !matches!(&self.cached, Some((cached_index, _)) if *cached_index == block_index) {
+            self.load_block(block_index)?;
+        }
+
+        let block = self.index.blocks()[block_index];
+        let (_, decompressed) = self.cached.as_ref().expect("block has just been loaded");
+
+        let skip = (self.position - block.uncompressed_offset()) as usize;
+        let available = &decompressed[skip..];
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableDecoderReader<R> {
+    /// Seek to an offset, in bytes, in the decompressed file
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.position = offset;
+                return Ok(offset);
+            }
+            SeekFrom::End(offset) => (self.index.uncompressed_len(), offset),
+            SeekFrom::Current(offset) => (self.position, offset),
+        };
+
+        let position = if offset >= 0 {
+            base.checked_add(offset as u64)
+        } else {
+            base.checked_sub(offset.unsigned_abs())
+        };
+
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
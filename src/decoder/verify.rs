@@ -0,0 +1,81 @@
+use std::io::{self, Read, Result};
+
+use super::{Decoder, ReadState};
+
+/// A summary of the data checked by [`Decoder::verify`], [`ParallelDecoder::verify`]
+/// or [`verify_reader`]
+///
+/// [`ParallelDecoder::verify`]: super::ParallelDecoder::verify
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifySummary {
+    pub(crate) blocks: u64,
+    pub(crate) uncompressed_len: u64,
+    pub(crate) stream_crcs: Vec<u32>,
+}
+
+impl VerifySummary {
+    /// The number of blocks which have been decoded, across all streams
+    pub fn blocks(&self) -> u64 {
+        self.blocks
+    }
+
+    /// The size of the decompressed data
+    pub fn uncompressed_len(&self) -> u64 {
+        self.uncompressed_len
+    }
+
+    /// The combined CRC of every stream which has been fully decoded, in order
+    pub fn stream_crcs(&self) -> &[u32] {
+        &self.stream_crcs
+    }
+}
+
+/// Check the integrity of the bzip2 file read from `reader`, like `bzip2 -t` does
+///
+/// The whole file is decoded and the CRC of every block and stream is
+/// verified, but the decompressed data is thrown away.
+///
+/// ```rust
+/// use std::fs::File;
+///
+/// use bzip2_rs::decoder::verify_reader;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let compressed_file = File::open("tests/samplefiles/sample2.bz2")?;
+///
+/// let summary = verify_reader(compressed_file)?;
+/// assert_eq!(summary.blocks(), 2);
+/// assert_eq!(summary.uncompressed_len(), 212340);
+/// #
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+///
+/// Returns the first decoding error, converted into an [`io::Error`] like
+/// [`DecoderReader`] does.
+///
+/// [`DecoderReader`]: crate::decoder::DecoderReader
+pub fn verify_reader<R: Read>(mut reader: R) -> Result<VerifySummary> {
+    let mut decoder = Decoder::new();
+
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match decoder.verify()? {
+            // `verify` only asks for more data once everything written to it has been decoded
+            ReadState::NeedsWrite if decoder.write_eof => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "The reader is empty?",
+                ));
+            }
+            ReadState::NeedsWrite => {
+                let read = reader.read(&mut buf)?;
+                decoder.write(&buf[..read]);
+            }
+            ReadState::Read(_) => unreachable!("verify doesn't return data"),
+            ReadState::Eof => return Ok(decoder.summary().clone()),
+        }
+    }
+}
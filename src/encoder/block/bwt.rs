@@ -0,0 +1,96 @@
+use std::mem;
+
+/// Compute the Burrows–Wheeler transform of `block`
+///
+/// The last column of the sorted rotation matrix is written into `out`,
+/// and the row at which the original `block` ended up is returned.
+///
+/// Rotations are sorted by prefix doubling: after every pass the rotations
+/// are ordered by their first `2 * k` bytes, until either all of them have
+/// a distinct rank or `k` covers the whole block.
+pub(crate) fn forward_bwt(block: &[u8], out: &mut Vec<u8>) -> u32 {
+    let n = block.len();
+    debug_assert!(n > 0);
+
+    let mut sa = vec![0u32; n];
+    let mut rank = vec![0u32; n];
+    let mut tmp = vec![0u32; n];
+
+    // sort by the first byte
+    let mut counts = vec![0u32; 256.max(n)];
+    for &b in block {
+        counts[usize::from(b)] += 1;
+    }
+    prefix_sum(&mut counts[..256]);
+    for (i, &b) in block.iter().enumerate().rev() {
+        counts[usize::from(b)] -= 1;
+        sa[counts[usize::from(b)] as usize] = i as u32;
+    }
+    for (r, &b) in rank.iter_mut().zip(block) {
+        *r = u32::from(b);
+    }
+    let mut classes = 256;
+
+    let mut k = 1;
+    while k < n {
+        // order by the second half of the key, which is the first half of
+        // the rotation starting `k` positions later
+        for (t, &s) in tmp.iter_mut().zip(&sa) {
+            *t = ((s as usize + n - k) % n) as u32;
+        }
+
+        // stable counting sort by the first half
+        counts[..classes].iter_mut().for_each(|count| *count = 0);
+        for &r in &rank {
+            counts[r as usize] += 1;
+        }
+        prefix_sum(&mut counts[..classes]);
+        for &t in tmp.iter().rev() {
+            let r = rank[t as usize] as usize;
+            counts[r] -= 1;
+            sa[counts[r] as usize] = t;
+        }
+
+        // re-rank, `tmp` becomes the new `rank`
+        let key = |i: u32| (rank[i as usize], rank[(i as usize + k) % n]);
+        tmp[sa[0] as usize] = 0;
+        let mut class = 0;
+        for w in sa.windows(2) {
+            if key(w[0]) != key(w[1]) {
+                class += 1;
+            }
+            tmp[w[1] as usize] = class;
+        }
+        mem::swap(&mut rank, &mut tmp);
+        classes = class as usize + 1;
+
+        if classes == n {
+            break;
+        }
+        k *= 2;
+    }
+
+    out.clear();
+    out.reserve(n);
+
+    let mut orig_ptr = 0;
+    for (i, &s) in sa.iter().enumerate() {
+        if s == 0 {
+            orig_ptr = i as u32;
+            out.push(block[n - 1]);
+        } else {
+            out.push(block[s as usize - 1]);
+        }
+    }
+
+    orig_ptr
+}
+
+/// Turn `counts` into the end index of each bucket
+fn prefix_sum(counts: &mut [u32]) {
+    let mut sum = 0;
+    for count in counts {
+        sum += *count;
+        *count = sum;
+    }
+}
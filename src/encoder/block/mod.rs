@@ -0,0 +1,422 @@
+//! bzip2 low-level block encoding
+
+use crate::bitwriter::BitWriter;
+use crate::crc::Hasher;
+use crate::decoder::block::BLOCK_MAGIC;
+use crate::header::Header;
+use crate::huffman;
+use crate::move_to_front::MoveToFrontEncoder;
+
+mod bwt;
+
+const RUN_A: u16 = 0;
+const RUN_B: u16 = 1;
+
+const GROUP_SIZE: usize = 50;
+const MAX_GROUPS: usize = 6;
+const MAX_CODE_LENGTH: u8 = 17;
+const ITERATIONS: usize = 4;
+
+/// Space reserved at the end of a block, so that flushing the
+/// pending RLE1 run can never make it exceed the maximum blocksize
+const BLOCK_OVERSHOOT: u32 = 19;
+
+/// A block of input data, after the initial run-length encoding step
+pub(crate) struct Block {
+    data: Vec<u8>,
+    max_len: usize,
+
+    // the RLE1 run being accumulated
+    run_byte: u8,
+    run_len: u8,
+
+    hasher: Hasher,
+}
+
+impl Block {
+    pub fn new(header: &Header) -> Self {
+        let max_len = (header.max_blocksize() - BLOCK_OVERSHOOT) as usize;
+
+        Self {
+            data: Vec::with_capacity(max_len),
+            max_len,
+
+            run_byte: 0,
+            run_len: 0,
+
+            hasher: Hasher::new(),
+        }
+    }
+
+    /// Run-length encode `buf` into this block
+    ///
+    /// Returns how many bytes of `buf` have been consumed, which is
+    /// less than `buf.len()` if the block is now full.
+    pub fn write(&mut self, buf: &[u8]) -> usize {
+        let mut consumed = 0;
+
+        for &b in buf {
+            if self.is_full() {
+                break;
+            }
+
+            if self.run_len > 0 && self.run_byte == b && self.run_len < 255 {
+                self.run_len += 1;
+            } else {
+                self.flush_run();
+                self.run_byte = b;
+                self.run_len = 1;
+            }
+
+            consumed += 1;
+        }
+
+        self.hasher.update(&buf[..consumed]);
+        consumed
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.data.len() >= self.max_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty() && self.run_len == 0
+    }
+
+    fn flush_run(&mut self) {
+        match self.run_len {
+            0 => {}
+            1..=3 => {
+                let new_len = self.data.len() + usize::from(self.run_len);
+                self.data.resize(new_len, self.run_byte);
+            }
+            _ => {
+                let new_len = self.data.len() + 4;
+                self.data.resize(new_len, self.run_byte);
+                self.data.push(self.run_len - 4);
+            }
+        }
+
+        self.run_len = 0;
+    }
+
+    /// Compress this block into `writer`, leaving it empty and ready
+    /// to accept the data for the next block
+    ///
+    /// Returns the CRC of the uncompressed data contained in the block.
+    pub fn compress(&mut self, writer: &mut BitWriter) -> u32 {
+        debug_assert!(!self.is_empty());
+
+        self.flush_run();
+
+        let crc = self.hasher.finalize();
+
+        let mut last_column = Vec::new();
+        let orig_ptr = bwt::forward_bwt(&self.data, &mut last_column);
+
+        let mut in_use = [false; 256];
+        for &b in &self.data {
+            in_use[usize::from(b)] = true;
+        }
+
+        writer.write_u64(48, BLOCK_MAGIC);
+        writer.write_bits(32, crc);
+        // randomised
+        writer.write_bit(false);
+        writer.write_bits(24, orig_ptr);
+
+        write_symbol_map(writer, &in_use);
+
+        let (symbols, alpha_size) = mtf_rle2(&last_column, &in_use);
+        write_huffman(writer, &symbols, alpha_size);
+
+        self.data.clear();
+        self.hasher = Hasher::new();
+
+        crc
+    }
+}
+
+fn write_symbol_map(writer: &mut BitWriter, in_use: &[bool; 256]) {
+    let mut ranges = 0u32;
+    for (i, range) in in_use.chunks_exact(16).enumerate() {
+        if range.iter().any(|&used| used) {
+            ranges |= 1 << (15 - i);
+        }
+    }
+    writer.write_bits(16, ranges);
+
+    for (i, range) in in_use.chunks_exact(16).enumerate() {
+        if ranges & (1 << (15 - i)) != 0 {
+            for &used in range {
+                writer.write_bit(used);
+            }
+        }
+    }
+}
+
+/// Apply the move-to-front transform and the zero run-length encoding to `last_column`
+///
+/// Returns the encoded symbols, terminated by the end of block symbol,
+/// and the size of the alphabet.
+fn mtf_rle2(last_column: &[u8], in_use: &[bool; 256]) -> (Vec<u16>, usize) {
+    // map the used bytes into a contiguous range
+    let mut seq = [0u8; 256];
+    let mut used = 0usize;
+    for (b, _) in in_use.iter().enumerate().filter(|(_, &used)| used) {
+        seq[b] = used as u8;
+        used += 1;
+    }
+    let alpha_size = used + 2;
+    let end_of_block = (used + 1) as u16;
+
+    let mut symbols = Vec::with_capacity(last_column.len() + 1);
+    let mut mtf = MoveToFrontEncoder::new();
+    let mut zeros = 0u32;
+
+    for &b in last_column {
+        let n = mtf.encode(seq[usize::from(b)]);
+
+        if n == 0 {
+            zeros += 1;
+        } else {
+            push_zero_run(&mut symbols, zeros);
+            zeros = 0;
+
+            symbols.push(u16::from(n) + 1);
+        }
+    }
+
+    push_zero_run(&mut symbols, zeros);
+    symbols.push(end_of_block);
+
+    (symbols, alpha_size)
+}
+
+/// Encode a run of `zeros` zeros using the bijective base-2 `RUNA`/`RUNB` symbols
+fn push_zero_run(symbols: &mut Vec<u16>, mut zeros: u32) {
+    if zeros == 0 {
+        return;
+    }
+
+    zeros -= 1;
+    loop {
+        symbols.push(if zeros & 1 == 0 { RUN_A } else { RUN_B });
+
+        if zeros < 2 {
+            break;
+        }
+        zeros = (zeros - 2) / 2;
+    }
+}
+
+fn write_huffman(writer: &mut BitWriter, symbols: &[u16], alpha_size: usize) {
+    let groups = match symbols.len() {
+        0..=199 => 2,
+        200..=599 => 3,
+        600..=1199 => 4,
+        1200..=2399 => 5,
+        _ => MAX_GROUPS,
+    };
+
+    let mut freqs = [0u32; 258];
+    for &symbol in symbols {
+        freqs[usize::from(symbol)] += 1;
+    }
+
+    let mut lengths = [[0u8; 258]; MAX_GROUPS];
+    initial_lengths(&mut lengths[..groups], &freqs[..alpha_size]);
+
+    // iteratively assign each group of symbols to the table that encodes it
+    // best, then rebuild the tables from the symbols assigned to them
+    let mut selectors = Vec::with_capacity((symbols.len() + GROUP_SIZE - 1) / GROUP_SIZE);
+    for _ in 0..ITERATIONS {
+        let mut table_freqs = [[0u32; 258]; MAX_GROUPS];
+        selectors.clear();
+
+        for group in symbols.chunks(GROUP_SIZE) {
+            let best = lengths[..groups]
+                .iter()
+                .map(|lengths| {
+                    group
+                        .iter()
+                        .map(|&symbol| u32::from(lengths[usize::from(symbol)]))
+                        .sum::<u32>()
+                })
+                .enumerate()
+                .min_by_key(|&(_, cost)| cost)
+                .map(|(i, _)| i)
+                .expect("at least 2 tables");
+
+            for &symbol in group {
+                table_freqs[best][usize::from(symbol)] += 1;
+            }
+            selectors.push(best as u8);
+        }
+
+        for (lengths, freqs) in lengths[..groups].iter_mut().zip(&table_freqs) {
+            huffman::code_lengths(
+                &freqs[..alpha_size],
+                &mut lengths[..alpha_size],
+                MAX_CODE_LENGTH,
+            );
+        }
+    }
+
+    writer.write_bits(3, groups as u32);
+    writer.write_bits(15, selectors.len() as u32);
+
+    let mut selectors_encoder = MoveToFrontEncoder::new();
+    for &selector in &selectors {
+        let n = selectors_encoder.encode(selector);
+        for _ in 0..n {
+            writer.write_bit(true);
+        }
+        writer.write_bit(false);
+    }
+
+    let mut codes = [[0u32; 258]; MAX_GROUPS];
+    for (lengths, codes) in lengths[..groups].iter().zip(&mut codes) {
+        let lengths = &lengths[..alpha_size];
+
+        let mut length = lengths[0];
+        writer.write_bits(5, u32::from(length));
+        for &target in lengths {
+            while length < target {
+                writer.write_bits(2, 0b10);
+                length += 1;
+            }
+            while length > target {
+                writer.write_bits(2, 0b11);
+                length -= 1;
+            }
+            writer.write_bit(false);
+        }
+
+        huffman::assign_codes(lengths, &mut codes[..alpha_size]);
+    }
+
+    for (group, &selector) in symbols.chunks(GROUP_SIZE).zip(&selectors) {
+        let lengths = &lengths[usize::from(selector)];
+        let codes = &codes[usize::from(selector)];
+
+        for &symbol in group {
+            let symbol = usize::from(symbol);
+            writer.write_bits(lengths[symbol], codes[symbol]);
+        }
+    }
+}
+
+/// Give every table a starting point, by splitting the alphabet into
+/// ranges of roughly equal total frequency and making each table
+/// cheap for its own range
+fn initial_lengths(lengths: &mut [[u8; 258]], freqs: &[u32]) {
+    const LESSER_COST: u8 = 0;
+    const GREATER_COST: u8 = 15;
+
+    let groups = lengths.len();
+    let alpha_size = freqs.len();
+
+    let mut remaining = freqs.iter().sum::<u32>();
+    let mut start = 0;
+
+    for part in (1..=groups).rev() {
+        let target = remaining / (part as u32);
+
+        let mut end = start;
+        let mut acc = 0;
+        while end < alpha_size && (acc < target || end == start) {
+            acc += freqs[end];
+            end += 1;
+        }
+
+        // alternate between rounding the ranges up and down
+        if end > start + 1 && part != groups && part != 1 && (groups - part) % 2 == 1 {
+            end -= 1;
+            acc -= freqs[end];
+        }
+
+        for (symbol, length) in lengths[part - 1][..alpha_size].iter_mut().enumerate() {
+            *length = if (start..end).contains(&symbol) {
+                LESSER_COST
+            } else {
+                GREATER_COST
+            };
+        }
+
+        start = end;
+        remaining -= acc;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitreader::BitReader;
+    use crate::decoder::block::Block as DecoderBlock;
+
+    fn roundtrip(input: &[u8], header: Header) {
+        let mut block = Block::new(&header);
+        let consumed = block.write(input);
+        assert_eq!(consumed, input.len());
+
+        let mut writer = BitWriter::new();
+        let crc = block.compress(&mut writer);
+        assert!(block.is_empty());
+        writer.align();
+
+        let mut expected_crc = Hasher::new();
+        expected_crc.update(input);
+        assert_eq!(crc, expected_crc.finalize());
+
+        let mut reader = BitReader::new([writer.bytes(), &[]]);
+        let mut decoder = DecoderBlock::new(header);
+        let mut out = vec![0u8; input.len() + 1];
+
+        decoder.set_ready_for_read();
+        let read = decoder.read(&mut reader, &mut out).unwrap();
+        assert_eq!(&out[..read], input);
+    }
+
+    #[test]
+    fn single_byte() {
+        roundtrip(b"a", Header::from_raw_blocksize(1).unwrap());
+    }
+
+    #[test]
+    fn runs() {
+        let mut input = Vec::new();
+        for len in 1..600 {
+            input.resize(input.len() + len, (len % 7) as u8);
+        }
+
+        roundtrip(&input, Header::from_raw_blocksize(9).unwrap());
+    }
+
+    #[test]
+    fn periodic() {
+        let input = b"abc".repeat(10_000);
+        roundtrip(&input, Header::from_raw_blocksize(1).unwrap());
+    }
+
+    #[test]
+    fn text() {
+        let input = include_bytes!("../../../tests/samplefiles/sample1.ref");
+        roundtrip(input, Header::from_raw_blocksize(1).unwrap());
+    }
+
+    #[test]
+    fn full_block() {
+        let header = Header::from_raw_blocksize(1).unwrap();
+        let input = (0..200_000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+            .collect::<Vec<u8>>();
+
+        let mut block = Block::new(&header);
+        let consumed = block.write(&input);
+        assert!(block.is_full());
+        assert!(consumed < input.len());
+
+        roundtrip(&input[..consumed], header);
+    }
+}
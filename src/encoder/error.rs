@@ -0,0 +1,32 @@
+use std::error::Error as StdError;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+/// An error returned by [`ParallelEncoder`]
+///
+/// [`ParallelEncoder`]: crate::encoder::ParallelEncoder
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum EncoderError {
+    /// The task compressing a block panicked, or has been dropped
+    /// by the [`ThreadPool`] without being run
+    ///
+    /// [`ThreadPool`]: crate::ThreadPool
+    BlockTaskFailed,
+}
+
+impl Display for EncoderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EncoderError::BlockTaskFailed => "the task compressing a block failed",
+        })
+    }
+}
+
+impl StdError for EncoderError {}
+
+impl From<EncoderError> for io::Error {
+    fn from(err: EncoderError) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, err)
+    }
+}
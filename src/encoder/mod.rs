@@ -0,0 +1,322 @@
+//! bzip2 encoding APIs
+
+use self::block::Block;
+pub use self::error::EncoderError;
+pub use self::parallel::{ParallelEncoder, ParallelEncoderWriter};
+pub use self::reader::EncoderReader;
+pub use self::state::ReadState;
+pub use self::writer::EncoderWriter;
+use crate::bitwriter::BitWriter;
+use crate::crc;
+use crate::decoder::block::FINAL_MAGIC;
+use crate::header::Header;
+
+mod block;
+mod error;
+mod parallel;
+mod reader;
+mod state;
+mod writer;
+
+/// A low-level **single-threaded** encoder implementation
+///
+/// This encoder does no IO by itself, instead uncompressed data
+/// has to be written to it until it has enough of it to fill
+/// the next block. After that the compressed content for the block
+/// can be read until all of the data from the block has been exhausted.
+/// Once all of the input has been written, writing an empty buffer
+/// signals the end of the input, after which the last block and the
+/// end of stream marker can be read.
+///
+/// ```rust
+/// use bzip2_rs::encoder::{Encoder, ReadState};
+/// use bzip2_rs::header::Header;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut decompressed_file: &[u8] = include_bytes!("../../tests/samplefiles/sample1.ref").as_ref();
+/// let mut output = Vec::new();
+///
+/// let mut encoder = Encoder::new(Header::from_raw_blocksize(9)?);
+///
+/// let mut buf = [0; 1024];
+/// loop {
+///     match encoder.read(&mut buf) {
+///         ReadState::NeedsWrite => {
+///             // `Encoder` needs more data to be written to it before it
+///             // can compress the next block.
+///             // If we reached the end of the input `decompressed_file.len()` will be 0,
+///             // signaling to the `Encoder` that the last block is smaller and it can
+///             // proceed with compressing it.
+///             encoder.write(decompressed_file);
+///             decompressed_file = &[];
+///         }
+///         ReadState::Read(n) => {
+///             // `n` compressed bytes have been read into `buf`
+///             output.extend_from_slice(&buf[..n]);
+///         }
+///         ReadState::Eof => {
+///             // we reached the end of the compressed stream
+///             break;
+///         }
+///     }
+/// }
+///
+/// // `output` contains the compressed file
+/// # let mut decoded = Vec::new();
+/// # std::io::copy(&mut bzip2_rs::DecoderReader::new(output.as_slice()), &mut decoded)?;
+/// # assert_eq!(decoded, include_bytes!("../../tests/samplefiles/sample1.ref").as_ref());
+/// #
+/// # Ok(())
+/// # }
+/// ```
+pub struct Encoder {
+    header: Header,
+    block: Block,
+
+    in_buf: Vec<u8>,
+    // the position in `in_buf` of the data which hasn't been written into `block` yet
+    in_pos: usize,
+    out_buf: BitWriter,
+
+    stream_crc: u32,
+
+    header_written: bool,
+    write_eof: bool,
+    eof: bool,
+}
+
+impl Encoder {
+    /// Construct a new [`Encoder`], ready to compress a new bzip2 file
+    ///
+    /// The blocksize declared by `header` is the maximum amount
+    /// of data compressed as a single block.
+    pub fn new(header: Header) -> Self {
+        Self {
+            block: Block::new(&header),
+            header,
+
+            in_buf: Vec::new(),
+            in_pos: 0,
+            out_buf: BitWriter::new(),
+
+            stream_crc: 0,
+
+            header_written: false,
+            write_eof: false,
+            eof: false,
+        }
+    }
+
+    /// Write more uncompressed data into this [`Encoder`]
+    ///
+    /// Writing an empty buffer signals the end of the input.
+    pub fn write(&mut self, buf: &[u8]) {
+        if !buf.is_empty() {
+            // get rid of the data which has already been written into `block`
+            if self.in_pos > 0 {
+                self.in_buf.drain(..self.in_pos);
+                self.in_pos = 0;
+            }
+
+            self.in_buf.extend_from_slice(buf);
+        } else {
+            self.write_eof = true;
+        }
+    }
+
+    /// Read more compressed data from this [`Encoder`]
+    ///
+    /// See the documentation for [`ReadState`] to decide
+    /// what to do next.
+    pub fn read(&mut self, buf: &mut [u8]) -> ReadState {
+        loop {
+            let available = self.out_buf.bytes();
+            if !available.is_empty() {
+                let read = available.len().min(buf.len());
+                buf[..read].copy_from_slice(&available[..read]);
+                self.out_buf.consume(read);
+
+                return ReadState::Read(read);
+            }
+
+            if self.eof {
+                return ReadState::Eof;
+            }
+
+            if !self.header_written {
+                write_stream_header(&mut self.out_buf, &self.header);
+                self.header_written = true;
+                continue;
+            }
+
+            self.in_pos += self.block.write(&self.in_buf[self.in_pos..]);
+            if self.in_pos == self.in_buf.len() {
+                self.in_buf.clear();
+                self.in_pos = 0;
+            }
+
+            if self.block.is_full() {
+                self.compress_block();
+            } else if self.write_eof {
+                if !self.block.is_empty() {
+                    self.compress_block();
+                }
+
+                write_stream_footer(&mut self.out_buf, self.stream_crc);
+                self.eof = true;
+            } else {
+                return ReadState::NeedsWrite;
+            }
+        }
+    }
+
+    fn compress_block(&mut self) {
+        let crc = self.block.compress(&mut self.out_buf);
+        self.stream_crc = crc::combine(self.stream_crc, crc);
+    }
+}
+
+fn write_stream_header(writer: &mut BitWriter, header: &Header) {
+    for &b in b"BZh" {
+        writer.write_bits(8, u32::from(b));
+    }
+    writer.write_bits(8, u32::from(b'0' + header.raw_blocksize()));
+}
+
+fn write_stream_footer(writer: &mut BitWriter, stream_crc: u32) {
+    writer.write_u64(48, FINAL_MAGIC);
+    writer.write_bits(32, stream_crc);
+    writer.align();
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new(Header::from_raw_blocksize(9).expect("9 is a valid blocksize"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::{Decoder, ReadState as DecoderReadState};
+
+    fn encode(mut input: &[u8], header: Header, chunk_size: usize) -> Vec<u8> {
+        let mut encoder = Encoder::new(header);
+        let mut output = Vec::new();
+
+        let mut buf = [0; 1024];
+        loop {
+            match encoder.read(&mut buf) {
+                ReadState::NeedsWrite => {
+                    let chunk = &input[..chunk_size.min(input.len())];
+                    encoder.write(chunk);
+                    input = &input[chunk.len()..];
+                }
+                ReadState::Read(n) => output.extend_from_slice(&buf[..n]),
+                ReadState::Eof => break,
+            }
+        }
+
+        output
+    }
+
+    fn decode(mut input: &[u8]) -> Vec<u8> {
+        let mut decoder = Decoder::new();
+        let mut output = Vec::new();
+
+        let mut buf = [0; 1024];
+        loop {
+            match decoder.read(&mut buf).unwrap() {
+                DecoderReadState::NeedsWrite => {
+                    decoder.write(input);
+                    input = &[];
+                }
+                DecoderReadState::Read(n) => output.extend_from_slice(&buf[..n]),
+                DecoderReadState::Eof => break,
+            }
+        }
+
+        output
+    }
+
+    #[test]
+    fn empty() {
+        let compressed = encode(&[], Header::from_raw_blocksize(3).unwrap(), 1024);
+        assert_eq!(
+            compressed,
+            [66, 90, 104, 51, 23, 114, 69, 56, 80, 144, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn sample1() {
+        let decompressed = include_bytes!("../../tests/samplefiles/sample1.ref");
+
+        let compressed = encode(decompressed, Header::from_raw_blocksize(1).unwrap(), 4096);
+        assert_eq!(&compressed[..4], b"BZh1");
+        assert_eq!(decode(&compressed), decompressed.as_ref());
+    }
+
+    #[test]
+    fn sample2_multiple_blocks() {
+        let decompressed = include_bytes!("../../tests/samplefiles/sample2.ref");
+
+        let compressed = encode(
+            decompressed,
+            Header::from_raw_blocksize(1).unwrap(),
+            100_000,
+        );
+        assert_eq!(decode(&compressed), decompressed.as_ref());
+    }
+
+    #[test]
+    fn sample3() {
+        let decompressed = include_bytes!("../../tests/samplefiles/sample3.ref");
+
+        let compressed = encode(decompressed, Header::from_raw_blocksize(9).unwrap(), 333);
+        assert_eq!(decode(&compressed), decompressed.as_ref());
+    }
+
+    #[test]
+    fn single_write() {
+        let decompressed = include_bytes!("../../tests/samplefiles/sample2.ref");
+
+        let compressed = encode(
+            decompressed,
+            Header::from_raw_blocksize(1).unwrap(),
+            usize::MAX,
+        );
+        assert_eq!(decode(&compressed), decompressed.as_ref());
+    }
+
+    #[test]
+    fn write_before_needs_write() {
+        let decompressed = include_bytes!("../../tests/samplefiles/sample2.ref");
+        let (first, second) = decompressed.split_at(150_000);
+
+        let mut encoder = Encoder::new(Header::from_raw_blocksize(1).unwrap());
+        let mut output = Vec::new();
+
+        // compress the first block, leaving the rest of `first` in the encoder
+        let mut buf = [0; 1024];
+        encoder.write(first);
+        while encoder.in_pos == 0 {
+            match encoder.read(&mut buf) {
+                ReadState::Read(n) => output.extend_from_slice(&buf[..n]),
+                state => panic!("unexpected state {:?}", state),
+            }
+        }
+
+        encoder.write(second);
+        encoder.write(&[]);
+        loop {
+            match encoder.read(&mut buf) {
+                ReadState::Read(n) => output.extend_from_slice(&buf[..n]),
+                ReadState::Eof => break,
+                ReadState::NeedsWrite => panic!("all of the input has been written"),
+            }
+        }
+
+        assert_eq!(decode(&output), decompressed.as_ref());
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+pub use self::writer::ParallelEncoderWriter;
+use super::block::Block;
+use super::{write_stream_footer, write_stream_header, EncoderError, ReadState};
+use crate::bitwriter::BitWriter;
+use crate::crc;
+use crate::header::Header;
+use crate::ThreadPool;
+
+mod writer;
+
+/// (block crc, compressed block), or `None` if the task compressing it failed
+type CompressedBlock = Option<(u32, BitWriter)>;
+/// (block index, `CompressedBlock`)
+type ChannelledBlock = (u32, CompressedBlock);
+
+/// A low-level **multi-threaded** encoder implementation
+///
+/// This encoder does no IO by itself, instead uncompressed data
+/// has to be written to it, which gets split into blocks that are
+/// then compressed concurrently. The compressed blocks can be read,
+/// in order, as a single bzip2 stream as soon as they're ready.
+/// Once all of the input has been written, writing an empty buffer
+/// signals the end of the input, after which the remaining blocks and
+/// the end of stream marker can be read.
+///
+/// ```rust
+/// # // A fake threadpool just to make the docs build when the `rayon` feature isn't enabled
+/// # #[cfg(not(feature = "rayon"))]
+/// # struct RayonThreadPool;
+/// #
+/// # #[cfg(not(feature = "rayon"))]
+/// # impl bzip2_rs::ThreadPool for RayonThreadPool {
+/// #     fn spawn<F>(&self, func: F)
+/// #     where
+/// #         F: FnOnce() + Send + 'static,
+/// #     {
+/// #         std::thread::spawn(func);
+/// #     }
+/// #
+/// #     fn max_threads(&self) -> std::num::NonZeroUsize {
+/// #         std::num::NonZeroUsize::new(1).unwrap()
+/// #     }
+/// # }
+/// #
+/// use bzip2_rs::encoder::{ParallelEncoder, ReadState};
+/// use bzip2_rs::header::Header;
+/// // use the rayon global threadpool as the threadpool for encoding this file.
+/// // requires the `rayon` feature to be enabled
+/// # #[cfg(feature = "rayon")]
+/// use bzip2_rs::RayonThreadPool;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut decompressed_file: &[u8] =
+///     include_bytes!("../../../tests/samplefiles/sample2.ref").as_ref();
+/// let mut output = Vec::new();
+///
+/// let mut encoder = ParallelEncoder::new(Header::from_raw_blocksize(1)?, RayonThreadPool, 4);
+///
+/// let mut buf = [0; 8192];
+/// loop {
+///     match encoder.read(&mut buf)? {
+///         ReadState::NeedsWrite => {
+///             // `ParallelEncoder` needs more data to be written to it before it
+///             // can schedule the next block.
+///             // If we reached the end of the input `decompressed_file.len()` will be 0,
+///             // signaling to the `ParallelEncoder` that the last block is smaller and it can
+///             // proceed with compressing it.
+///             encoder.write(decompressed_file);
+///             decompressed_file = &[];
+///         }
+///         ReadState::Read(n) => {
+///             // `n` compressed bytes have been read into `buf`
+///             output.extend_from_slice(&buf[..n]);
+///         }
+///         ReadState::Eof => {
+///             // we reached the end of the compressed stream
+///             break;
+///         }
+///     }
+/// }
+///
+/// // `output` contains the compressed file
+/// # let mut decoded = Vec::new();
+/// # std::io::copy(&mut bzip2_rs::DecoderReader::new(output.as_slice()), &mut decoded)?;
+/// # assert_eq!(decoded, include_bytes!("../../../tests/samplefiles/sample2.ref").as_ref());
+/// #
+/// # Ok(())
+/// # }
+/// ```
+pub struct ParallelEncoder<P> {
+    header: Header,
+    block: Block,
+
+    in_buf: Vec<u8>,
+    // the position in `in_buf` of the data which hasn't been written into `block` yet
+    in_pos: usize,
+    out_buf: BitWriter,
+
+    pool: P,
+    sender: Sender<ChannelledBlock>,
+    receiver: Receiver<ChannelledBlock>,
+    // the next block index to be scheduled - can wrap
+    next_index: u32,
+    // the next block index expected to be received
+    receive_index: u32,
+    receive_pool: BTreeMap<u32, CompressedBlock>,
+
+    max_in_flight: usize,
+
+    stream_crc: u32,
+
+    header_written: bool,
+    write_eof: bool,
+    eof: bool,
+}
+
+impl<P> ParallelEncoder<P> {
+    /// Construct a new [`ParallelEncoder`], ready to compress a new bzip2 file
+    ///
+    /// Compared to [`Encoder`], this encoder implements multi-threaded encoding.
+    /// Every block is compressed by a separate task spawned into `pool`, and the
+    /// results are concatenated in order into a single bzip2 stream, readable
+    /// by any bzip2 decoder.
+    ///
+    /// [`ParallelEncoder`] takes `P` as a generic argument, which is the [`ThreadPool`]
+    /// implementation used for spawning tasks. If the `rayon` feature is enabled,
+    /// [`RayonThreadPool`] can be used, with internally uses the `rayon` global
+    /// threadpool for spawning tasks.
+    ///
+    /// `max_in_flight` defines how many blocks can be compressing, or waiting to be read,
+    /// at the same time. Since every block takes up to the blocksize declared by
+    /// `header` of memory, this bounds the memory used by the encoder.
+    /// A value of `0` is treated as `1`, while using a value lower than the number of
+    /// threads available in `pool` will prevent some of them from being used.
+    ///
+    /// [`Encoder`]: crate::encoder::Encoder
+    /// [`RayonThreadPool`]: crate::RayonThreadPool
+    pub fn new(header: Header, pool: P, max_in_flight: usize) -> Self {
+        let (sender, receiver) = channel::<ChannelledBlock>();
+
+        Self {
+            block: Block::new(&header),
+            header,
+
+            in_buf: Vec::new(),
+            in_pos: 0,
+            out_buf: BitWriter::new(),
+
+            pool,
+            sender,
+            receiver,
+            next_index: 0,
+            receive_index: 0,
+            receive_pool: BTreeMap::new(),
+
+            max_in_flight: max_in_flight.max(1),
+
+            stream_crc: 0,
+
+            header_written: false,
+            write_eof: false,
+            eof: false,
+        }
+    }
+
+    /// Write more uncompressed data into this [`ParallelEncoder`]
+    ///
+    /// Writing an empty buffer signals the end of the input.
+    pub fn write(&mut self, buf: &[u8]) {
+        if !buf.is_empty() {
+            // get rid of the data which has already been written into `block`
+            if self.in_pos > 0 {
+                self.in_buf.drain(..self.in_pos);
+                self.in_pos = 0;
+            }
+
+            self.in_buf.extend_from_slice(buf);
+        } else {
+            self.write_eof = true;
+        }
+    }
+}
+
+impl<P: ThreadPool> ParallelEncoder<P> {
+    /// Read more compressed data from this [`ParallelEncoder`]
+    ///
+    /// See the documentation for [`ReadState`] to decide
+    /// what to do next. Returns [`EncoderError::BlockTaskFailed`] if the task
+    /// compressing the next block failed, in which case the stream can't be completed.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<ReadState, EncoderError> {
+        loop {
+            let available = self.out_buf.bytes();
+            if !available.is_empty() {
+                let read = available.len().min(buf.len());
+                buf[..read].copy_from_slice(&available[..read]);
+                self.out_buf.consume(read);
+
+                return Ok(ReadState::Read(read));
+            }
+
+            if self.eof {
+                return Ok(ReadState::Eof);
+            }
+
+            if !self.header_written {
+                write_stream_header(&mut self.out_buf, &self.header);
+                self.header_written = true;
+                continue;
+            }
+
+            // collect the blocks that have already been compressed
+            while let Ok((index, compressed)) = self.receiver.try_recv() {
+                self.receive_pool.insert(index, compressed);
+            }
+            if self.take_next_block()? {
+                continue;
+            }
+
+            self.in_pos += self.block.write(&self.in_buf[self.in_pos..]);
+            if self.in_pos == self.in_buf.len() {
+                self.in_buf.clear();
+                self.in_pos = 0;
+            }
+
+            let schedule = self.block.is_full() || (self.write_eof && !self.block.is_empty());
+            let in_flight = self.next_index.wrapping_sub(self.receive_index) as usize;
+
+            if schedule && in_flight < self.max_in_flight {
+                self.schedule_block();
+            } else if schedule || (self.write_eof && in_flight > 0) {
+                // wait for the next block in order to be compressed
+                self.receive_next_block()?;
+            } else if self.write_eof {
+                write_stream_footer(&mut self.out_buf, self.stream_crc);
+                self.eof = true;
+            } else {
+                return Ok(ReadState::NeedsWrite);
+            }
+        }
+    }
+
+    fn schedule_block(&mut self) {
+        let block = mem::replace(&mut self.block, Block::new(&self.header));
+        let sender = self.sender.clone();
+
+        let block_index = self.next_index;
+        self.next_index = self.next_index.wrapping_add(1);
+
+        let block_sender = BlockSender {
+            sender,
+            block_index,
+            compressed: None,
+        };
+        self.pool.spawn(move || {
+            let mut block = block;
+            let mut compressed = BitWriter::new();
+            let crc = block.compress(&mut compressed);
+
+            block_sender.send(crc, compressed);
+        });
+    }
+
+    fn receive_next_block(&mut self) -> Result<(), EncoderError> {
+        while !self.receive_pool.contains_key(&self.receive_index) {
+            // every task sends its block, even if it fails
+            let (index, compressed) = self.receiver.recv().expect("the encoder holds a sender");
+            self.receive_pool.insert(index, compressed);
+        }
+
+        self.take_next_block().map(|_| ())
+    }
+
+    /// Append the next block to the output, if it has already been compressed
+    fn take_next_block(&mut self) -> Result<bool, EncoderError> {
+        match self.receive_pool.get(&self.receive_index) {
+            // the failed block is left in place, so that every following read fails
+            Some(None) => Err(EncoderError::BlockTaskFailed),
+            Some(Some(_)) => {
+                let (crc, compressed) = self
+                    .receive_pool
+                    .remove(&self.receive_index)
+                    .flatten()
+                    .expect("the block is present");
+                self.out_buf.append(&compressed);
+                self.stream_crc = crc::combine(self.stream_crc, crc);
+                self.receive_index = self.receive_index.wrapping_add(1);
+
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// Sends the result of the task compressing a block when dropped
+///
+/// If the task panics, or is dropped by the [`ThreadPool`] without being run,
+/// `None` is sent instead of the compressed block, so that the encoder
+/// doesn't wait for it forever.
+struct BlockSender {
+    sender: Sender<ChannelledBlock>,
+    block_index: u32,
+    compressed: CompressedBlock,
+}
+
+impl BlockSender {
+    fn send(mut self, crc: u32, compressed: BitWriter) {
+        self.compressed = Some((crc, compressed));
+    }
+}
+
+impl Drop for BlockSender {
+    fn drop(&mut self) {
+        let _ = self.sender.send((self.block_index, self.compressed.take()));
+    }
+}
@@ -0,0 +1,135 @@
+use std::io::{Result, Write};
+
+use super::{EncoderError, ParallelEncoder, ReadState};
+use crate::encoder::writer::{InnerWriter, SansIoEncoder};
+use crate::header::Header;
+use crate::ThreadPool;
+
+/// A high-level **multi-threaded** encoder that wraps a [`Write`] and implements [`Write`], compressing the written bytes
+///
+/// The end of stream marker is only written by [`ParallelEncoderWriter::finish`] or
+/// [`ParallelEncoderWriter::try_finish`]. If the `ParallelEncoderWriter` is dropped
+/// without having been finished, the stream is finished by the [`Drop`]
+/// implementation, but any error that occurs while doing so is silently
+/// ignored. Call [`ParallelEncoderWriter::finish`] in order to be notified of them.
+///
+/// ```rust
+/// # // A fake threadpool just to make the docs build when the `rayon` feature isn't enabled
+/// # #[cfg(not(feature = "rayon"))]
+/// # struct RayonThreadPool;
+/// #
+/// # #[cfg(not(feature = "rayon"))]
+/// # impl bzip2_rs::ThreadPool for RayonThreadPool {
+/// #     fn spawn<F>(&self, func: F)
+/// #     where
+/// #         F: FnOnce() + Send + 'static,
+/// #     {
+/// #         std::thread::spawn(func);
+/// #     }
+/// #
+/// #     fn max_threads(&self) -> std::num::NonZeroUsize {
+/// #         std::num::NonZeroUsize::new(1).unwrap()
+/// #     }
+/// # }
+/// #
+/// use std::fs::File;
+/// use std::io;
+///
+/// use bzip2_rs::header::Header;
+/// use bzip2_rs::ParallelEncoderWriter;
+/// // use the rayon global threadpool as the threadpool for encoding this file.
+/// // requires the `rayon` feature to be enabled
+/// # #[cfg(feature = "rayon")]
+/// use bzip2_rs::RayonThreadPool;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut decompressed_file = File::open("tests/samplefiles/sample2.ref")?;
+/// # fn no_run() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut output = File::create("output.bz2")?;
+/// # Ok(())
+/// # }
+/// # let mut output = Vec::new();
+///
+/// let mut writer =
+///     ParallelEncoderWriter::new(output, Header::from_raw_blocksize(9)?, RayonThreadPool, 8);
+/// io::copy(&mut decompressed_file, &mut writer)?;
+/// let output = writer.finish()?;
+/// #
+/// # let mut decompressed = Vec::new();
+/// # io::copy(&mut bzip2_rs::DecoderReader::new(output.as_slice()), &mut decompressed)?;
+/// # let expected = std::fs::read("tests/samplefiles/sample2.ref")?;
+/// # assert_eq!(expected, decompressed);
+/// #
+/// # Ok(())
+/// # }
+/// ```
+pub struct ParallelEncoderWriter<W: Write, P: ThreadPool> {
+    inner: InnerWriter<ParallelEncoder<P>, W>,
+}
+
+impl<W: Write, P: ThreadPool> ParallelEncoderWriter<W, P> {
+    /// Construct a new encoder, writing the compressed data into `writer`
+    ///
+    /// Compared to [`EncoderWriter`], this encoder implements multi-threaded encoding,
+    /// compressing each block in a separate task spawned into `pool`.
+    ///
+    /// [`ParallelEncoderWriter`] takes `P` as a generic argument, which is the [`ThreadPool`]
+    /// implementation used for spawning tasks. If the `rayon` feature is enabled,
+    /// [`RayonThreadPool`] can be used, with internally uses the `rayon` global
+    /// threadpool for spawning tasks.
+    ///
+    /// `max_in_flight` defines how many blocks can be compressing, or waiting to be written,
+    /// at the same time. See [`ParallelEncoder::new`] for more details.
+    ///
+    /// [`EncoderWriter`]: crate::EncoderWriter
+    /// [`RayonThreadPool`]: crate::RayonThreadPool
+    pub fn new(writer: W, header: Header, pool: P, max_in_flight: usize) -> Self {
+        Self {
+            inner: InnerWriter::new(
+                ParallelEncoder::new(header.clone(), pool, max_in_flight),
+                writer,
+                &header,
+            ),
+        }
+    }
+
+    /// Compress the remaining data and write the end of stream marker
+    ///
+    /// After calling this method any further write is going to fail.
+    /// Calling it again after it succeeded is a no-op.
+    pub fn try_finish(&mut self) -> Result<()> {
+        self.inner.try_finish()
+    }
+
+    /// Finish the stream, as in [`ParallelEncoderWriter::try_finish`], and return the inner writer
+    pub fn finish(self) -> Result<W> {
+        self.inner.finish()
+    }
+}
+
+impl<W: Write, P: ThreadPool> Write for ParallelEncoderWriter<W, P> {
+    /// Compress `buf` into the underlying writer
+    ///
+    /// At most the blocksize declared by the header of the stream is accepted at once.
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.inner.write(buf)
+    }
+
+    /// Write the compressed data which is already available and flush the underlying writer
+    ///
+    /// This doesn't wait for the blocks being compressed to be finished,
+    /// nor forces the current block to be compressed.
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<P: ThreadPool> SansIoEncoder for ParallelEncoder<P> {
+    fn write(&mut self, buf: &[u8]) {
+        ParallelEncoder::write(self, buf);
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> std::result::Result<ReadState, EncoderError> {
+        ParallelEncoder::read(self, buf)
+    }
+}
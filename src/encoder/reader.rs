@@ -0,0 +1,99 @@
+#[cfg(feature = "nightly")]
+use std::io::BorrowedBuf;
+use std::io::{Read, Result};
+#[cfg(feature = "nightly")]
+use std::mem::MaybeUninit;
+
+use super::{Encoder, ReadState};
+use crate::header::Header;
+
+/// A high-level **single-threaded** encoder that wraps a [`Read`] and implements [`Read`], yielding compressed bytes
+///
+/// Data is pulled from the underlying reader as it's needed
+/// to fill the next block, so at most one block of uncompressed
+/// data is kept in memory at any time.
+///
+/// ```rust
+/// use std::fs::File;
+/// use std::io;
+///
+/// use bzip2_rs::header::Header;
+/// use bzip2_rs::EncoderReader;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut decompressed_file = File::open("tests/samplefiles/sample1.ref")?;
+/// # fn no_run() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut output = File::create("output.bz2")?;
+/// # Ok(())
+/// # }
+/// # let mut output = Vec::new();
+///
+/// let mut reader = EncoderReader::new(decompressed_file, Header::from_raw_blocksize(9)?);
+/// io::copy(&mut reader, &mut output)?;
+/// #
+/// # let mut decompressed = Vec::new();
+/// # io::copy(&mut bzip2_rs::DecoderReader::new(output.as_slice()), &mut decompressed)?;
+/// # let expected = std::fs::read("tests/samplefiles/sample1.ref")?;
+/// # assert_eq!(expected, decompressed);
+/// #
+/// # Ok(())
+/// # }
+/// ```
+pub struct EncoderReader<R> {
+    encoder: Encoder,
+
+    reader: R,
+}
+
+impl<R> EncoderReader<R> {
+    /// Construct a new encoder from something implementing [`Read`]
+    ///
+    /// The blocksize declared by `header` is the maximum amount
+    /// of data compressed as a single block.
+    pub fn new(reader: R, header: Header) -> Self {
+        Self {
+            encoder: Encoder::new(header),
+
+            reader,
+        }
+    }
+}
+
+impl<R: Read> Read for EncoderReader<R> {
+    /// Compress data from the underlying reader
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        #[cfg(not(feature = "nightly"))]
+        let mut tmp_buf = [0; 1024];
+        #[cfg(feature = "nightly")]
+        let mut tmp_buf = [MaybeUninit::uninit(); 1024];
+        #[cfg(feature = "nightly")]
+        let mut read_buf = BorrowedBuf::from(tmp_buf.as_mut_slice());
+
+        loop {
+            match self.encoder.read(buf) {
+                ReadState::NeedsWrite => {
+                    #[cfg(feature = "nightly")]
+                    let read = {
+                        read_buf.clear();
+                        self.reader.read_buf(read_buf.unfilled())?;
+                        read_buf.filled()
+                    };
+                    #[cfg(not(feature = "nightly"))]
+                    let read = {
+                        let n = self.reader.read(&mut tmp_buf)?;
+                        &tmp_buf[..n]
+                    };
+
+                    // an empty `read` signals the end of the input to `Encoder`
+                    self.encoder.write(read);
+                }
+                ReadState::Read(n) => return Ok(n),
+                ReadState::Eof => return Ok(0),
+            }
+        }
+    }
+}
@@ -0,0 +1,19 @@
+/// State returned by [`Encoder::read`]
+///
+/// [`Encoder::read`]: super::Encoder::read
+#[derive(Debug)]
+pub enum ReadState {
+    /// Not enough data has been written to the underlying [`Encoder`]
+    /// in order to allow the next block to be compressed. Call
+    /// [`Encoder::write`] to write more data. If the end of the input
+    /// has been reached, call [`Encoder::write`] with an empty buffer.
+    ///
+    /// [`Encoder`]: super::Encoder
+    /// [`Encoder::write`]: super::Encoder::write
+    NeedsWrite,
+    /// N. number of compressed bytes have been read
+    Read(usize),
+    /// The end of the compressed stream has been reached and
+    /// there is no more data to read
+    Eof,
+}
@@ -0,0 +1,267 @@
+use std::io::{self, Result, Write};
+use std::thread;
+
+use super::{Encoder, EncoderError, ReadState};
+use crate::header::Header;
+
+/// A high-level **single-threaded** encoder that wraps a [`Write`] and implements [`Write`], compressing the written bytes
+///
+/// The end of stream marker is only written by [`EncoderWriter::finish`] or
+/// [`EncoderWriter::try_finish`]. If the `EncoderWriter` is dropped without
+/// having been finished, the stream is finished by the [`Drop`]
+/// implementation, but any error that occurs while doing so is silently
+/// ignored. Call [`EncoderWriter::finish`] in order to be notified of them.
+///
+/// ```rust
+/// use std::fs::File;
+/// use std::io;
+///
+/// use bzip2_rs::header::Header;
+/// use bzip2_rs::EncoderWriter;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut decompressed_file = File::open("tests/samplefiles/sample1.ref")?;
+/// # fn no_run() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut output = File::create("output.bz2")?;
+/// # Ok(())
+/// # }
+/// # let mut output = Vec::new();
+///
+/// let mut writer = EncoderWriter::new(output, Header::from_raw_blocksize(9)?);
+/// io::copy(&mut decompressed_file, &mut writer)?;
+/// let output = writer.finish()?;
+/// #
+/// # let mut decompressed = Vec::new();
+/// # io::copy(&mut bzip2_rs::DecoderReader::new(output.as_slice()), &mut decompressed)?;
+/// # let expected = std::fs::read("tests/samplefiles/sample1.ref")?;
+/// # assert_eq!(expected, decompressed);
+/// #
+/// # Ok(())
+/// # }
+/// ```
+pub struct EncoderWriter<W: Write> {
+    inner: InnerWriter<Encoder, W>,
+}
+
+impl<W: Write> EncoderWriter<W> {
+    /// Construct a new encoder, writing the compressed data into `writer`
+    ///
+    /// The blocksize declared by `header` is the maximum amount
+    /// of data compressed as a single block.
+    pub fn new(writer: W, header: Header) -> Self {
+        Self {
+            inner: InnerWriter::new(Encoder::new(header.clone()), writer, &header),
+        }
+    }
+
+    /// Compress the remaining data and write the end of stream marker
+    ///
+    /// After calling this method any further write is going to fail.
+    /// Calling it again after it succeeded is a no-op.
+    pub fn try_finish(&mut self) -> Result<()> {
+        self.inner.try_finish()
+    }
+
+    /// Finish the stream, as in [`EncoderWriter::try_finish`], and return the inner writer
+    pub fn finish(self) -> Result<W> {
+        self.inner.finish()
+    }
+}
+
+impl<W: Write> Write for EncoderWriter<W> {
+    /// Compress `buf` into the underlying writer
+    ///
+    /// At most the blocksize declared by the header of the stream is accepted at once.
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.inner.write(buf)
+    }
+
+    /// Write the compressed data which is already available and flush the underlying writer
+    ///
+    /// This doesn't force the current block to be compressed,
+    /// since doing so would require starting a new block.
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A Sans I/O encoder, whose compressed data can be written by [`InnerWriter`]
+pub(crate) trait SansIoEncoder {
+    /// Write more uncompressed data into the encoder
+    ///
+    /// Writing an empty buffer signals the end of the input.
+    fn write(&mut self, buf: &[u8]);
+
+    /// Read more compressed data from the encoder
+    fn read(&mut self, buf: &mut [u8]) -> std::result::Result<ReadState, EncoderError>;
+}
+
+impl SansIoEncoder for Encoder {
+    fn write(&mut self, buf: &[u8]) {
+        Encoder::write(self, buf);
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> std::result::Result<ReadState, EncoderError> {
+        Ok(Encoder::read(self, buf))
+    }
+}
+
+/// The [`Write`] implementation shared by [`EncoderWriter`] and [`ParallelEncoderWriter`],
+/// compressing the written bytes through `E`
+///
+/// [`ParallelEncoderWriter`]: crate::ParallelEncoderWriter
+pub(crate) struct InnerWriter<E: SansIoEncoder, W: Write> {
+    encoder: E,
+
+    // the maximum amount of data accepted by a single write
+    max_write_len: usize,
+
+    writer: Option<W>,
+    // compressed data which hasn't been written into `writer` yet
+    pending: Vec<u8>,
+    // the end of the input has been signaled to `encoder`
+    finishing: bool,
+    finished: bool,
+}
+
+impl<E: SansIoEncoder, W: Write> InnerWriter<E, W> {
+    pub(crate) fn new(encoder: E, writer: W, header: &Header) -> Self {
+        Self {
+            encoder,
+            max_write_len: header.max_blocksize() as usize,
+
+            writer: Some(writer),
+            pending: Vec::new(),
+            finishing: false,
+            finished: false,
+        }
+    }
+
+    pub(crate) fn try_finish(&mut self) -> Result<()> {
+        if !self.finished {
+            if !self.finishing {
+                self.encoder.write(&[]);
+                self.finishing = true;
+            }
+            self.dump()?;
+
+            self.finished = true;
+        }
+
+        self.writer_mut().flush()
+    }
+
+    pub(crate) fn finish(mut self) -> Result<W> {
+        self.try_finish()?;
+
+        Ok(self.writer.take().expect("writer is only taken by finish"))
+    }
+
+    /// Write all of the compressed data available in `encoder` into the inner writer
+    fn dump(&mut self) -> Result<()> {
+        self.try_dump()?
+    }
+
+    /// Write all of the compressed data available in `encoder` into the inner writer
+    ///
+    /// The errors of `encoder` are returned in the outer [`Result`], while
+    /// the ones of the inner writer are returned in the inner one.
+    fn try_dump(&mut self) -> std::result::Result<Result<()>, EncoderError> {
+        let mut buf = [0; 8192];
+
+        loop {
+            if let Err(err) = self.write_pending() {
+                return Ok(Err(err));
+            }
+
+            match self.encoder.read(&mut buf)? {
+                ReadState::NeedsWrite | ReadState::Eof => return Ok(Ok(())),
+                ReadState::Read(n) => self.pending.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    /// Write the compressed data which hasn't been written yet into the inner writer
+    ///
+    /// Whatever couldn't be written is kept for the next attempt.
+    fn write_pending(&mut self) -> Result<()> {
+        let writer = self
+            .writer
+            .as_mut()
+            .expect("writer is only taken by finish");
+
+        let mut written = 0;
+        let result = loop {
+            if written == self.pending.len() {
+                break Ok(());
+            }
+
+            match writer.write(&self.pending[written..]) {
+                Ok(0) => {
+                    break Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write the compressed data",
+                    ))
+                }
+                Ok(n) => written += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => break Err(err),
+            }
+        };
+
+        self.pending.drain(..written);
+        result
+    }
+
+    fn writer_mut(&mut self) -> &mut W {
+        self.writer
+            .as_mut()
+            .expect("writer is only taken by finish")
+    }
+}
+
+impl<E: SansIoEncoder, W: Write> Write for InnerWriter<E, W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        // even if finishing failed, `encoder` doesn't accept any more data
+        if self.finishing {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "the stream has already been finished",
+            ));
+        }
+
+        if buf.is_empty() {
+            // an empty write would signal the end of the input to the encoder
+            return Ok(0);
+        }
+
+        // errors writing the data compressed so far are reported
+        // before `buf` gets accepted, so that retrying doesn't duplicate it
+        self.write_pending()?;
+
+        // at most a block is accepted at once, so that the
+        // encoder doesn't have to buffer all of `buf`
+        let buf = &buf[..buf.len().min(self.max_write_len)];
+
+        self.encoder.write(buf);
+        // `buf` has been accepted, so a failure to write the compressed data is
+        // only reported by the next call, after which the data is written again.
+        // A failed block can't be recovered from, so it's reported right away
+        let _ = self.try_dump()?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.dump()?;
+        self.writer_mut().flush()
+    }
+}
+
+impl<E: SansIoEncoder, W: Write> Drop for InnerWriter<E, W> {
+    fn drop(&mut self) {
+        if self.writer.is_some() && !thread::panicking() {
+            let _ = self.try_finish();
+        }
+    }
+}
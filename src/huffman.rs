@@ -1,7 +1,29 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
 use tinyvec::ArrayVec;
 
+use crate::bitreader::BitReader;
+
+/// The longest code allowed by bzip2
+const MAX_CODE_LENGTH: u8 = 20;
+/// The number of bits used for indexing the primary table
+const PRIMARY_BITS: u8 = 10;
+
+/// Marks table entries pointing to a sub table
+const SUB_TABLE: u32 = 0x80;
+const LENGTH_MASK: u32 = 0x1f;
+
+/// A huffman decoder, decoding multiple bits at once through lookup tables
+///
+/// Every entry of the tables is either a symbol, stored as `value << 8 | length`,
+/// or a pointer to a sub table, stored as `offset << 8 | SUB_TABLE | bits`.
+/// Codes up to [`PRIMARY_BITS`] long are decoded through the primary table alone,
+/// while longer ones require looking up the bits following the first [`PRIMARY_BITS`]
+/// into the sub table.
 pub struct HuffmanTree {
-    nodes: [HuffmanNode; 258],
+    primary: [u32; 1 << PRIMARY_BITS],
+    secondary: Vec<u32>,
 }
 
 #[derive(Default, Copy, Clone)]
@@ -30,121 +52,207 @@ struct HuffmanCode {
 
 impl HuffmanTree {
     pub fn new(lengths: &[u8]) -> Result<Self, &'static str> {
-        assert!(lengths.len() >= 2, "too few symbols");
+        let nodes = build_nodes(lengths)?;
 
-        let mut pairs = lengths
-            .iter()
-            .enumerate()
-            .map(|(i, &length)| LengthPair {
-                value: i as u16,
-                length,
-            })
-            .collect::<ArrayVec<[LengthPair; 258]>>();
+        let mut this = HuffmanTree::default();
+        this.fill_primary(&nodes, 0, 0, 0);
+        Ok(this)
+    }
 
-        pairs.sort_unstable_by(|a, b| a.length.cmp(&b.length).then_with(|| a.value.cmp(&b.value)));
+    pub fn decode(&self, reader: &mut BitReader<'_>) -> Option<u16> {
+        let bits = reader.peek_bits(MAX_CODE_LENGTH);
 
-        let mut code = 0u32;
-        let mut length = 32u8;
+        let mut entry = self.primary[(bits >> (MAX_CODE_LENGTH - PRIMARY_BITS)) as usize];
+        if entry & SUB_TABLE != 0 {
+            let sub_bits = entry & LENGTH_MASK;
+            let offset = entry >> 8;
 
-        let mut codes = pairs
-            .into_iter()
-            .rev()
-            .map(|pair| {
-                length = length.min(pair.length);
+            let index = (bits >> (u32::from(MAX_CODE_LENGTH - PRIMARY_BITS) - sub_bits))
+                & ((1 << sub_bits) - 1);
+            entry = self.secondary[(offset + index) as usize];
+        }
 
-                let c = HuffmanCode {
-                    code,
-                    value: pair.value,
-                };
+        reader.consume((entry & LENGTH_MASK) as u8)?;
+        Some((entry >> 8) as u16)
+    }
 
-                code = code.wrapping_add(1u32.rotate_right(u32::from(length)));
-                c
-            })
-            .collect::<ArrayVec<[HuffmanCode; 258]>>();
+    /// Fill the primary table with the leaves under `node`, reached by reading the `depth` bits of `prefix`
+    fn fill_primary(&mut self, nodes: &[HuffmanNode], node: u16, prefix: u32, depth: u8) {
+        for (bit, state) in nodes[usize::from(node)].right_left.iter().enumerate() {
+            let prefix = (prefix << 1) | bit as u32;
+            let depth = depth + 1;
 
-        codes.sort_unstable_by(|a, b| a.code.cmp(&b.code));
+            match *state {
+                HuffmanNodeState::Done(value) => {
+                    let entry = (u32::from(value) << 8) | u32::from(depth);
+                    fill_range(&mut self.primary, PRIMARY_BITS, prefix, depth, entry);
+                }
+                HuffmanNodeState::Next(next) if depth == PRIMARY_BITS => {
+                    let sub_bits = subtree_depth(nodes, next);
+                    let offset = self.secondary.len();
+                    self.secondary.resize(offset + (1 << sub_bits), 0);
 
-        let mut this = HuffmanTree::default();
-        Self::build_huffman_node(&mut this.nodes[..codes.len()], &mut 0, &codes, 0)?;
-        Ok(this)
+                    self.primary[prefix as usize] =
+                        ((offset as u32) << 8) | SUB_TABLE | u32::from(sub_bits);
+                    fill_secondary(&mut self.secondary[offset..], nodes, next, sub_bits, 0, 0);
+                }
+                HuffmanNodeState::Next(next) => self.fill_primary(nodes, next, prefix, depth),
+            }
+        }
     }
+}
 
-    pub fn decode<I>(&self, bits_iter: &mut I) -> Option<u16>
-    where
-        I: Iterator<Item = bool>,
-    {
-        let mut node = &self.nodes[0];
+/// Fill the sub table `table` with the leaves under `node`, reached by reading
+/// [`PRIMARY_BITS`] bits followed by the `depth` bits of `prefix`
+fn fill_secondary(
+    table: &mut [u32],
+    nodes: &[HuffmanNode],
+    node: u16,
+    table_bits: u8,
+    prefix: u32,
+    depth: u8,
+) {
+    for (bit, state) in nodes[usize::from(node)].right_left.iter().enumerate() {
+        let prefix = (prefix << 1) | bit as u32;
+        let depth = depth + 1;
 
-        for bit in bits_iter {
-            let val = &node.right_left[usize::from(bit)];
-            match val {
-                HuffmanNodeState::Next(node_index) => node = &self.nodes[usize::from(*node_index)],
-                HuffmanNodeState::Done(value) => return Some(*value),
-            };
+        match *state {
+            HuffmanNodeState::Done(value) => {
+                let entry = (u32::from(value) << 8) | u32::from(PRIMARY_BITS + depth);
+                fill_range(table, table_bits, prefix, depth, entry);
+            }
+            HuffmanNodeState::Next(next) => {
+                fill_secondary(table, nodes, next, table_bits, prefix, depth)
+            }
         }
-
-        None
     }
+}
 
-    fn build_huffman_node(
-        nodes: &mut [HuffmanNode],
-        next_node: &mut usize,
-        codes: &[HuffmanCode],
-        level: u32,
-    ) -> Result<u16, &'static str> {
-        let test = 1u32.rotate_right(level);
+/// Set `entry` for every index of `table` starting with the `depth` bits of `prefix`
+fn fill_range(table: &mut [u32], table_bits: u8, prefix: u32, depth: u8, entry: u32) {
+    let free_bits = table_bits - depth;
+    let start = (prefix << free_bits) as usize;
+    for slot in &mut table[start..start + (1 << free_bits)] {
+        *slot = entry;
+    }
+}
 
-        let first_right_index = codes
-            .iter()
-            .enumerate()
-            .find(|(_, code)| code.code & test != 0)
-            .map_or(codes.len(), |(i, _)| i);
+/// The number of bits needed for reaching the deepest leaf under `node`
+fn subtree_depth(nodes: &[HuffmanNode], node: u16) -> u8 {
+    nodes[usize::from(node)]
+        .right_left
+        .iter()
+        .map(|state| match *state {
+            HuffmanNodeState::Done(_) => 1,
+            HuffmanNodeState::Next(next) => 1 + subtree_depth(nodes, next),
+        })
+        .max()
+        .unwrap()
+}
 
-        let (left, right) = codes.split_at(first_right_index);
+/// Build the binary tree of the code with the given `lengths`, whose root is the first node
+///
+/// Reading a `1` bit goes to the left child of a node, while a `0` bit goes to the right one.
+fn build_nodes(lengths: &[u8]) -> Result<[HuffmanNode; 258], &'static str> {
+    assert!(lengths.len() >= 2, "too few symbols");
+    debug_assert!(lengths.iter().all(|&length| length <= MAX_CODE_LENGTH));
 
-        if left.is_empty() || right.is_empty() {
-            if codes.len() < 2 {
-                return Err("empty huffman tree");
-            }
+    let mut pairs = lengths
+        .iter()
+        .enumerate()
+        .map(|(i, &length)| LengthPair {
+            value: i as u16,
+            length,
+        })
+        .collect::<ArrayVec<[LengthPair; 258]>>();
 
-            if level == 31 {
-                return Err("equal symbols in huffman tree");
-            }
+    pairs.sort_unstable_by(|a, b| a.length.cmp(&b.length).then_with(|| a.value.cmp(&b.value)));
 
-            if left.is_empty() {
-                Self::build_huffman_node(nodes, next_node, right, level + 1)
-            } else {
-                Self::build_huffman_node(nodes, next_node, left, level + 1)
-            }
-        } else {
-            let node_index = *next_node;
-            *next_node += 1;
+    let mut code = 0u32;
+    let mut length = 32u8;
 
-            nodes[node_index].right_left[1] = if left.len() == 1 {
-                HuffmanNodeState::Done(left[0].value)
-            } else {
-                let val = Self::build_huffman_node(nodes, next_node, left, level + 1)?;
+    let mut codes = pairs
+        .into_iter()
+        .rev()
+        .map(|pair| {
+            length = length.min(pair.length);
 
-                HuffmanNodeState::Next(val)
+            let c = HuffmanCode {
+                code,
+                value: pair.value,
             };
 
-            nodes[node_index].right_left[0] = if right.len() == 1 {
-                HuffmanNodeState::Done(right[0].value)
-            } else {
-                let val = Self::build_huffman_node(nodes, next_node, right, level + 1)?;
+            code = code.wrapping_add(1u32.rotate_right(u32::from(length)));
+            c
+        })
+        .collect::<ArrayVec<[HuffmanCode; 258]>>();
 
-                HuffmanNodeState::Next(val)
-            };
+    codes.sort_unstable_by_key(|a| a.code);
+
+    let mut nodes = [HuffmanNode::default(); 258];
+    build_huffman_node(&mut nodes[..codes.len()], &mut 0, &codes, 0)?;
+    Ok(nodes)
+}
+
+fn build_huffman_node(
+    nodes: &mut [HuffmanNode],
+    next_node: &mut usize,
+    codes: &[HuffmanCode],
+    level: u32,
+) -> Result<u16, &'static str> {
+    let test = 1u32.rotate_right(level);
+
+    let first_right_index = codes
+        .iter()
+        .enumerate()
+        .find(|(_, code)| code.code & test != 0)
+        .map_or(codes.len(), |(i, _)| i);
+
+    let (left, right) = codes.split_at(first_right_index);
+
+    if left.is_empty() || right.is_empty() {
+        if codes.len() < 2 {
+            return Err("empty huffman tree");
+        }
 
-            Ok(node_index as u16)
+        if level == 31 {
+            return Err("equal symbols in huffman tree");
         }
+
+        if left.is_empty() {
+            build_huffman_node(nodes, next_node, right, level + 1)
+        } else {
+            build_huffman_node(nodes, next_node, left, level + 1)
+        }
+    } else {
+        let node_index = *next_node;
+        *next_node += 1;
+
+        nodes[node_index].right_left[1] = if left.len() == 1 {
+            HuffmanNodeState::Done(left[0].value)
+        } else {
+            let val = build_huffman_node(nodes, next_node, left, level + 1)?;
+
+            HuffmanNodeState::Next(val)
+        };
+
+        nodes[node_index].right_left[0] = if right.len() == 1 {
+            HuffmanNodeState::Done(right[0].value)
+        } else {
+            let val = build_huffman_node(nodes, next_node, right, level + 1)?;
+
+            HuffmanNodeState::Next(val)
+        };
+
+        Ok(node_index as u16)
     }
 }
 
 impl Default for HuffmanTree {
     fn default() -> Self {
         Self {
-            nodes: [HuffmanNode::default(); 258],
+            primary: [0; 1 << PRIMARY_BITS],
+            secondary: Vec::new(),
         }
     }
 }
@@ -154,3 +262,273 @@ impl Default for HuffmanNodeState {
         HuffmanNodeState::Next(0)
     }
 }
+
+/// Compute the huffman code length for each symbol in `freqs`, making sure
+/// that none of them is longer than `max_length`.
+///
+/// Symbols with a frequency of zero are still assigned a code, since bzip2
+/// requires every symbol of the alphabet to have one.
+pub fn code_lengths(freqs: &[u32], lengths: &mut [u8], max_length: u8) {
+    debug_assert!(freqs.len() >= 2);
+    debug_assert_eq!(freqs.len(), lengths.len());
+
+    let mut weights = freqs
+        .iter()
+        .map(|&freq| freq.max(1))
+        .collect::<ArrayVec<[u32; 258]>>();
+
+    loop {
+        // every node is identified by its index: leaves come first,
+        // followed by the internal nodes in the order they're created
+        let mut parents = ArrayVec::<[u16; 516]>::new();
+        parents.set_len(weights.len());
+
+        // (weight, depth, node index)
+        let mut heap = weights
+            .iter()
+            .enumerate()
+            .map(|(i, &weight)| Reverse((u64::from(weight), 0u8, i as u16)))
+            .collect::<BinaryHeap<_>>();
+
+        while heap.len() > 1 {
+            let Reverse((weight1, depth1, node1)) = heap.pop().unwrap();
+            let Reverse((weight2, depth2, node2)) = heap.pop().unwrap();
+
+            let node = parents.len() as u16;
+            parents.push(0);
+            parents[usize::from(node1)] = node;
+            parents[usize::from(node2)] = node;
+
+            heap.push(Reverse((weight1 + weight2, depth1.max(depth2) + 1, node)));
+        }
+
+        let root = parents.len() - 1;
+        let mut too_long = false;
+        for (i, length) in lengths.iter_mut().enumerate() {
+            let mut node = i;
+            let mut depth = 0u8;
+            while node != root {
+                node = usize::from(parents[node]);
+                depth += 1;
+            }
+
+            *length = depth;
+            too_long |= depth > max_length;
+        }
+
+        if !too_long {
+            return;
+        }
+
+        // flatten the distribution and try again
+        for weight in &mut weights {
+            *weight = 1 + *weight / 2;
+        }
+    }
+}
+
+/// Assign canonical huffman codes to symbols with the given `lengths`
+pub fn assign_codes(lengths: &[u8], codes: &mut [u32]) {
+    debug_assert_eq!(lengths.len(), codes.len());
+
+    let min_length = lengths.iter().copied().min().unwrap_or(0);
+    let max_length = lengths.iter().copied().max().unwrap_or(0);
+
+    let mut code = 0u32;
+    for length in min_length..=max_length {
+        for (&symbol_length, symbol_code) in lengths.iter().zip(codes.iter_mut()) {
+            if symbol_length == length {
+                *symbol_code = code;
+                code += 1;
+            }
+        }
+
+        code <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_lengths_limit() {
+        // fibonacci frequencies produce the deepest possible trees
+        let mut freqs = [0u32; 30];
+        let (mut a, mut b) = (1, 1);
+        for freq in &mut freqs {
+            *freq = a;
+            let c = a + b;
+            a = b;
+            b = c;
+        }
+
+        let mut lengths = [0u8; 30];
+        code_lengths(&freqs, &mut lengths, 17);
+        assert!(lengths.iter().all(|&length| (1..=17).contains(&length)));
+
+        // the resulting code must be complete
+        let kraft = lengths
+            .iter()
+            .map(|&length| 1u32 << (17 - length))
+            .sum::<u32>();
+        assert_eq!(kraft, 1 << 17);
+    }
+
+    #[test]
+    fn roundtrip_codes() {
+        let freqs = [10, 0, 3, 7, 1, 1, 25, 4];
+        let mut lengths = [0u8; 8];
+        code_lengths(&freqs, &mut lengths, 17);
+
+        let mut codes = [0u32; 8];
+        assign_codes(&lengths, &mut codes);
+
+        let tree = HuffmanTree::new(&lengths).unwrap();
+        for (symbol, (&length, &code)) in lengths.iter().zip(codes.iter()).enumerate() {
+            let bytes = (code << (32 - length)).to_be_bytes();
+            let mut reader = BitReader::new([&bytes[..usize::from(length + 7) / 8], &[]]);
+            assert_eq!(tree.decode(&mut reader), Some(symbol as u16));
+            assert_eq!(reader.position(), u32::from(length));
+        }
+    }
+
+    /// Decode a symbol by walking the tree one bit at a time
+    fn tree_walk(nodes: &[HuffmanNode], reader: &mut BitReader<'_>) -> Option<u16> {
+        let mut node = &nodes[0];
+
+        while let Some(bit) = reader.read_bit() {
+            match node.right_left[usize::from(bit)] {
+                HuffmanNodeState::Next(node_index) => node = &nodes[usize::from(node_index)],
+                HuffmanNodeState::Done(value) => return Some(value),
+            }
+        }
+
+        None
+    }
+
+    fn pseudo_random_bytes(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    fn fibonacci_lengths(symbols: usize, max_length: u8) -> Vec<u8> {
+        let mut freqs = vec![0u32; symbols];
+        let (mut a, mut b) = (1u32, 1u32);
+        for freq in &mut freqs {
+            *freq = a;
+            let c = a.saturating_add(b);
+            a = b;
+            b = c;
+        }
+
+        let mut lengths = vec![0u8; symbols];
+        code_lengths(&freqs, &mut lengths, max_length);
+        lengths
+    }
+
+    #[test]
+    fn table_matches_tree_walk() {
+        let bytes = pseudo_random_bytes(4096);
+
+        let length_sets = [
+            fibonacci_lengths(258, 20),
+            fibonacci_lengths(40, 20),
+            fibonacci_lengths(21, 12),
+            vec![8; 256],
+            vec![1, 2],
+            // incomplete and oversubscribed codes are accepted by bzip2
+            vec![1, 3, 3],
+            vec![2, 2, 2, 2, 2, 20],
+            vec![1, 1, 1],
+        ];
+
+        for lengths in &length_sets {
+            let (tree, nodes) = match (HuffmanTree::new(lengths), build_nodes(lengths)) {
+                (Ok(tree), Ok(nodes)) => (tree, nodes),
+                (Err(a), Err(b)) => {
+                    assert_eq!(a, b);
+                    continue;
+                }
+                _ => unreachable!(),
+            };
+
+            let mut table_reader = BitReader::new([&bytes, &[]]);
+            let mut tree_reader = BitReader::new([&bytes, &[]]);
+            loop {
+                let symbol = tree.decode(&mut table_reader);
+                assert_eq!(symbol, tree_walk(&nodes, &mut tree_reader));
+                if symbol.is_none() {
+                    break;
+                }
+
+                assert_eq!(table_reader.position(), tree_reader.position());
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_lengths() {
+        assert_eq!(
+            HuffmanTree::new(&[1, 1, 1]).err(),
+            Some("equal symbols in huffman tree")
+        );
+        assert!(HuffmanTree::new(&[20; 258]).is_ok());
+    }
+
+    #[cfg(feature = "nightly")]
+    mod bench {
+        extern crate test;
+
+        use self::test::Bencher;
+        use super::*;
+
+        fn setup() -> (Vec<u8>, Vec<u8>) {
+            let mut lengths = vec![0u8; 258];
+            let freqs = pseudo_random_bytes(258)
+                .iter()
+                .map(|&b| u32::from(b) * u32::from(b) + 1)
+                .collect::<Vec<_>>();
+            code_lengths(&freqs, &mut lengths, 17);
+
+            (lengths, pseudo_random_bytes(64 * 1024))
+        }
+
+        #[bench]
+        fn decode_table(b: &mut Bencher) {
+            let (lengths, bytes) = setup();
+            let tree = HuffmanTree::new(&lengths).unwrap();
+
+            b.iter(|| {
+                let mut reader = BitReader::new([test::black_box(&bytes), &[]]);
+                let mut sum = 0u32;
+                while let Some(symbol) = tree.decode(&mut reader) {
+                    sum += u32::from(symbol);
+                }
+                sum
+            });
+        }
+
+        #[bench]
+        fn decode_tree_walk(b: &mut Bencher) {
+            let (lengths, bytes) = setup();
+            let nodes = build_nodes(&lengths).unwrap();
+
+            b.iter(|| {
+                let mut reader = BitReader::new([test::black_box(&bytes), &[]]);
+                let mut sum = 0u32;
+                while let Some(symbol) = tree_walk(&nodes, &mut reader) {
+                    sum += u32::from(symbol);
+                }
+                sum
+            });
+        }
+    }
+}
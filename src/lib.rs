@@ -1,4 +1,4 @@
-//! `bzip2_rs` is a pure Rust bzip2 decoder.
+//! `bzip2_rs` is a pure Rust bzip2 decoder and encoder.
 //!
 //! ## Main APIs
 //!
@@ -12,11 +12,31 @@
 //! * [`ParallelDecoder`]: low-level, Sans I/O, bzip2 decoder
 //! * [`ParallelDecoderReader`]: high-level synchronous bzip2 decoder
 //!
+//! ### One-shot decompression
+//!
+//! * [`decompress`] and [`decompress_into`]: decompress a bzip2 file held in memory
+//! * [`decompress_parallel`]: multi-threaded version of [`decompress`]
+//!
+//! ### Single-threaded encoder
+//!
+//! * [`Encoder`]: low-level, Sans I/O, bzip2 encoder
+//! * [`EncoderWriter`]: high-level synchronous bzip2 encoder, wrapping a [`Write`]
+//! * [`EncoderReader`]: high-level synchronous bzip2 encoder, wrapping a [`Read`]
+//!
+//! ### Multi-threaded encoder
+//!
+//! * [`ParallelEncoder`]: low-level, Sans I/O, bzip2 encoder
+//! * [`ParallelEncoderWriter`]: high-level synchronous bzip2 encoder
+//!
 //! ## Features
 //!
-//! * `rayon`: enable using the [rayon] global threadpool for parallel decoding.
-//!            NOTE: this feature is not subject to the normal MSRV. At the time
-//!            of writing the MSRV for rayon is 1.63
+//! * `rayon`: enable using the [rayon] global threadpool for parallel decoding and encoding.
+//!   NOTE: this feature is not subject to the normal MSRV. At the time
+//!   of writing the MSRV for rayon is 1.63
+//! * `simd-crc`: compute the block CRC through [crc-fast], folding it with carry-less
+//!   multiplication instructions instead of looking it up in tables.
+//!   NOTE: this feature breaks both the MSRV, requiring Rust >= 1.89, and the
+//!   100% safe guarantee, as crc-fast is built on `unsafe` SIMD code
 //!
 //! * Default features: Rust >= 1.63 is supported
 //! * `nightly`: require Rust Nightly, enable more optimizations
@@ -41,7 +61,12 @@
 //!
 //! [`Decoder`]: crate::decoder::Decoder
 //! [`ParallelDecoder`]: crate::decoder::ParallelDecoder
+//! [`Encoder`]: crate::encoder::Encoder
+//! [`ParallelEncoder`]: crate::encoder::ParallelEncoder
+//! [`Write`]: std::io::Write
+//! [`Read`]: std::io::Read
 //! [rayon]: https://crates.io/crates/rayon
+//! [crc-fast]: https://crates.io/crates/crc-fast
 
 #![deny(
     trivial_casts,
@@ -55,17 +80,24 @@
 #![forbid(unsafe_code)]
 #![allow(clippy::mem_replace_with_default)]
 #![cfg_attr(feature = "nightly", feature(read_buf, core_io_borrowed_buf))]
+#![cfg_attr(all(test, feature = "nightly"), feature(test))]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
 #[doc(no_inline)]
-pub use self::decoder::{DecoderReader, ParallelDecoderReader};
+pub use self::decoder::{decompress, decompress_into, decompress_parallel};
+#[doc(no_inline)]
+pub use self::decoder::{DecoderReader, ParallelDecoderReader, SeekableDecoderReader};
+#[doc(no_inline)]
+pub use self::encoder::{EncoderReader, EncoderWriter, ParallelEncoderWriter};
 #[cfg(feature = "rayon")]
 pub use self::threadpool::RayonThreadPool;
 pub use self::threadpool::ThreadPool;
 
 mod bitreader;
+mod bitwriter;
 mod crc;
 pub mod decoder;
+pub mod encoder;
 pub mod header;
 mod huffman;
 mod move_to_front;
@@ -1,7 +1,7 @@
-use tinyvec::{ArrayVec, SliceVec};
+use tinyvec::SliceVec;
 
 use crate::bitreader::BitReader;
-use crate::decoder::block::BlockError;
+use crate::decoder::block::{BlockError, BlockErrorKind, Field};
 
 pub struct MoveToFrontDecoder {
     symbols: [u8; 256],
@@ -27,32 +27,27 @@ impl MoveToFrontDecoder {
     pub fn read_from_block(reader: &mut BitReader<'_>) -> Result<(Self, usize), BlockError> {
         let mut this = Self::new_zeroed();
 
-        let mut bitmaps = ArrayVec::<[u8; 16]>::new();
-
-        for i in 0..16 {
-            if reader
-                .next()
-                .ok_or_else(|| BlockError::new("symbol range truncated"))?
-            {
-                bitmaps.push(i);
-            }
-        }
+        let ranges = reader
+            .read_u16(16)
+            .ok_or_else(|| BlockError::truncated(Field::SymbolMap))?;
 
         let mut symbols = SliceVec::from_slice_len(&mut this.symbols, 0);
 
-        for symbol_range in bitmaps {
-            for symbol in 0..16 {
-                if reader
-                    .next()
-                    .ok_or_else(|| BlockError::new("symbol range truncated"))?
-                {
-                    symbols.push(symbol_range * 16 + symbol);
-                }
+        for symbol_range in (0..16).filter(|i| ranges & (0x8000 >> i) != 0) {
+            let used = reader
+                .read_u16(16)
+                .ok_or_else(|| BlockError::truncated(Field::SymbolMap))?;
+
+            for symbol in (0..16).filter(|i| used & (0x8000 >> i) != 0) {
+                symbols.push(symbol_range * 16 + symbol);
             }
         }
 
         if symbols.is_empty() {
-            return Err(BlockError::new("no symbols in input"));
+            return Err(BlockError::new(
+                BlockErrorKind::NoSymbols,
+                "no symbols in input",
+            ));
         }
 
         let alpha_size = symbols.len() + 2;
@@ -96,6 +91,35 @@ impl MoveToFrontDecoder {
     }
 }
 
+pub struct MoveToFrontEncoder {
+    symbols: [u8; 256],
+}
+
+impl MoveToFrontEncoder {
+    pub fn new() -> Self {
+        let mut symbols = [0u8; 256];
+
+        for (i, symbol) in symbols.iter_mut().enumerate() {
+            *symbol = i as u8;
+        }
+
+        Self { symbols }
+    }
+
+    pub fn encode(&mut self, b: u8) -> u8 {
+        let n = self
+            .symbols
+            .iter()
+            .position(|&symbol| symbol == b)
+            .expect("every byte is in the list");
+
+        self.symbols.copy_within(..n, 1);
+        self.symbols[0] = b;
+
+        n as u8
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -128,4 +152,15 @@ mod tests {
             assert_eq!(i, usize::from(decoder.symbols[i]));
         }
     }
+
+    #[test]
+    fn encode_decode() {
+        let mut encoder = MoveToFrontEncoder::new();
+        let mut decoder = MoveToFrontDecoder::new();
+
+        for &b in b"bananaaa\x00\xffbanana" {
+            let n = encoder.encode(b);
+            assert_eq!(decoder.decode(n), b);
+        }
+    }
 }
@@ -0,0 +1,27 @@
+use std::io::{self, Write};
+
+/// A writer which writes at most 1000 bytes at a time, and fails
+/// `failures` times in a row after `fail_after` successful writes
+pub struct FailingWriter {
+    pub out: Vec<u8>,
+    pub fail_after: usize,
+    pub failures: usize,
+}
+
+impl Write for FailingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.fail_after == 0 && self.failures > 0 {
+            self.failures -= 1;
+            return Err(io::Error::new(io::ErrorKind::Other, "failed"));
+        }
+        self.fail_after = self.fail_after.saturating_sub(1);
+
+        let n = buf.len().min(1000);
+        self.out.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,6 +1,11 @@
+use std::convert::TryInto;
 use std::io::{self, Read};
 
-use bzip2_rs::decoder::DecoderReader;
+use bzip2_rs::decoder::block::{BlockErrorKind, Location};
+use bzip2_rs::decoder::{
+    verify_reader, BlockIndex, BufferPool, Decoder, DecoderError, DecoderOptions, DecoderReader,
+    Limit, Limits, ReadState,
+};
 
 #[test]
 fn empty() {
@@ -25,8 +30,8 @@ fn bad_num_selectors() {
 
     let mut buf = [0; 1024];
     let err = reader.read(&mut buf).unwrap_err();
-    assert_eq!(err.kind(), io::ErrorKind::Other);
-    assert_eq!(err.to_string(), "too high value for num_selectors");
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "block: too high value for num_selectors");
 }
 
 #[test]
@@ -46,8 +51,8 @@ fn trees_overflow() {
 
     let mut buf = [0; 1024];
     let err = reader.read(&mut buf).unwrap_err();
-    assert_eq!(err.kind(), io::ErrorKind::Other);
-    assert_eq!(err.to_string(), "tree index too large");
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "block: tree index too large");
 }
 
 #[test]
@@ -91,3 +96,441 @@ fn sample3() {
     assert_eq!(decompressed.len(), out.len());
     assert_eq!(decompressed.as_ref(), out.as_slice());
 }
+
+#[test]
+fn sample2_randomised() {
+    let compressed = include_bytes!("samplefiles/sample2_randomised.bz2");
+    let decompressed = include_bytes!("samplefiles/sample2.ref");
+
+    let mut reader = DecoderReader::new(compressed.as_ref());
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+
+    assert_eq!(decompressed.len(), out.len());
+    assert_eq!(decompressed.as_ref(), out.as_slice());
+}
+
+#[test]
+fn sample3_randomised() {
+    let compressed = include_bytes!("samplefiles/sample3_randomised.bz2");
+    let decompressed = include_bytes!("samplefiles/sample3.ref");
+
+    let mut reader = DecoderReader::new(compressed.as_ref());
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+
+    assert_eq!(decompressed.len(), out.len());
+    assert_eq!(decompressed.as_ref(), out.as_slice());
+}
+
+#[test]
+fn multiple_streams() {
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample3.bz2"));
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample2.bz2"));
+
+    let mut decompressed = include_bytes!("samplefiles/sample1.ref").to_vec();
+    decompressed.extend_from_slice(include_bytes!("samplefiles/sample3.ref"));
+    decompressed.extend_from_slice(include_bytes!("samplefiles/sample2.ref"));
+
+    let mut reader = DecoderReader::new(compressed.as_slice());
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+
+    assert_eq!(decompressed.len(), out.len());
+    assert_eq!(decompressed, out);
+}
+
+#[test]
+fn many_empty_streams() {
+    const EMPTY_STREAM: &[u8] = &[66, 90, 104, 51, 23, 114, 69, 56, 80, 144, 0, 0, 0, 0];
+
+    let mut compressed = EMPTY_STREAM.repeat(50_000);
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample1.bz2"));
+    let decompressed = include_bytes!("samplefiles/sample1.ref");
+
+    // write all of the streams at once, so that they are all handled by the same read call
+    let mut decoder = Decoder::new();
+    decoder.write(&compressed);
+    decoder.write(&[]);
+
+    let mut out = Vec::new();
+    let mut buf = [0; 1024];
+    loop {
+        match decoder.read(&mut buf).unwrap() {
+            ReadState::NeedsWrite => unreachable!(),
+            ReadState::Read(n) => out.extend_from_slice(&buf[..n]),
+            ReadState::Eof => break,
+        }
+    }
+
+    assert_eq!(decompressed.len(), out.len());
+    assert_eq!(decompressed.as_ref(), out.as_slice());
+}
+
+#[test]
+fn single_stream() {
+    let sample3 = include_bytes!("samplefiles/sample3.bz2");
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    compressed.extend_from_slice(sample3);
+    let decompressed = include_bytes!("samplefiles/sample1.ref");
+
+    let mut reader = DecoderReader::new(compressed.as_slice());
+    reader.set_multi_stream(false);
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+
+    assert_eq!(decompressed.len(), out.len());
+    assert_eq!(decompressed.as_ref(), out.as_slice());
+
+    // the second stream has been partially read into the decoder
+    let mut rest = reader.remaining_input().to_vec();
+    assert!(!rest.is_empty());
+    rest.extend_from_slice(reader.get_ref());
+    assert_eq!(rest, sample3.as_ref());
+}
+
+#[test]
+fn decode_written_blocks() {
+    let compressed = include_bytes!("samplefiles/sample1.bz2");
+    let decompressed = include_bytes!("samplefiles/sample1.ref");
+
+    let mut decoder = Decoder::new();
+    let mut chunks = compressed.chunks(100);
+    let mut out = Vec::new();
+    let mut buf = [0; 1024];
+    loop {
+        match decoder.read(&mut buf).unwrap() {
+            ReadState::NeedsWrite => match chunks.next() {
+                Some(chunk) => decoder.write(chunk),
+                None => panic!("the end of the file was never needed"),
+            },
+            ReadState::Read(n) => out.extend_from_slice(&buf[..n]),
+            ReadState::Eof => unreachable!("the end of the file hasn't been written"),
+        }
+
+        if out.len() == decompressed.len() {
+            break;
+        }
+    }
+
+    // the block is decoded as soon as all of it has been written
+    assert_eq!(out, decompressed.as_ref());
+}
+
+#[test]
+fn trailing_garbage() {
+    let mut compressed = include_bytes!("samplefiles/sample3.bz2").to_vec();
+    compressed.extend_from_slice(b"this is not a bzip2 stream");
+    let decompressed = include_bytes!("samplefiles/sample3.ref");
+
+    let mut reader = DecoderReader::new(compressed.as_slice());
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+
+    assert_eq!(decompressed.len(), out.len());
+    assert_eq!(decompressed.as_ref(), out.as_slice());
+}
+
+#[test]
+fn bad_stream_crc() {
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    // the second to last byte is always part of the stream crc
+    let crc_byte = compressed.len() - 2;
+    compressed[crc_byte] ^= 0xff;
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample3.bz2"));
+
+    let mut reader = DecoderReader::new(compressed.as_slice());
+
+    let mut out = Vec::new();
+    let err = reader.read_to_end(&mut out).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().starts_with("bad stream crc"));
+}
+
+#[test]
+fn error_location() {
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    let second_stream = compressed.len() as u64 * 8;
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample2.bz2"));
+
+    // corrupt the crc of the second block of sample2,
+    // whose magic starts 544888 bits into the stream
+    let second_block = second_stream + 544888;
+    compressed[((second_block + 48) / 8) as usize] ^= 0x08;
+
+    let mut reader = DecoderReader::new(compressed.as_slice());
+
+    let mut out = Vec::new();
+    let err = reader.read_to_end(&mut out).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let err = err
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<DecoderError>()
+        .unwrap();
+    match err {
+        DecoderError::Block(err) => assert!(matches!(err.kind(), BlockErrorKind::BadCrc { .. })),
+        err => panic!("unexpected error: {}", err),
+    }
+    assert_eq!(
+        err.location(),
+        Some(Location {
+            bit_offset: second_block,
+            block_index: 1,
+        })
+    );
+}
+
+#[test]
+fn truncated() {
+    let compressed = include_bytes!("samplefiles/sample1.bz2");
+    let compressed = &compressed[..compressed.len() / 2];
+
+    let mut reader = DecoderReader::new(compressed);
+
+    let mut out = Vec::new();
+    let err = reader.read_to_end(&mut out).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    let err = err
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<DecoderError>()
+        .unwrap();
+    match err {
+        DecoderError::Block(err) => assert!(matches!(err.kind(), BlockErrorKind::Truncated(_))),
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn verify() {
+    let sample1 = include_bytes!("samplefiles/sample1.bz2");
+    let sample3 = include_bytes!("samplefiles/sample3.bz2");
+    let mut compressed = sample1.to_vec();
+    compressed.extend_from_slice(sample3);
+
+    let summary = verify_reader(compressed.as_slice()).unwrap();
+    assert_eq!(summary.blocks(), 2);
+    assert_eq!(summary.uncompressed_len(), 98696 + 120244);
+    // the stream crc of a single block stream is the crc of its block
+    assert_eq!(
+        summary.stream_crcs(),
+        &[
+            u32::from_be_bytes(sample1[10..14].try_into().unwrap()),
+            u32::from_be_bytes(sample3[10..14].try_into().unwrap())
+        ]
+    );
+}
+
+#[test]
+fn verify_sans_io() {
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample2.bz2"));
+
+    let mut decoder = Decoder::new();
+    decoder.write(&compressed);
+    decoder.write(&[]);
+
+    // every block has already been written, so there's nothing left to wait for
+    assert!(matches!(decoder.verify().unwrap(), ReadState::Eof));
+    assert_eq!(decoder.summary().blocks(), 3);
+    assert_eq!(decoder.summary().uncompressed_len(), 98696 + 212340);
+}
+
+#[test]
+fn verify_bad_crc() {
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    // the first byte of the crc of the first block
+    compressed[10] ^= 0xff;
+
+    let err = verify_reader(compressed.as_slice()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn skip_corrupt_blocks() {
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    let second_stream = compressed.len() as u64 * 8;
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample2.bz2"));
+    let sample1 = include_bytes!("samplefiles/sample1.ref");
+    let sample2 = include_bytes!("samplefiles/sample2.ref");
+
+    // damage the first block of sample2
+    compressed[(second_stream / 8) as usize + 1000] ^= 0xff;
+
+    let mut reader = DecoderReader::new(compressed.as_slice());
+    reader.set_skip_corrupt_blocks(true);
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+
+    // only the second block of sample2 is left after sample1
+    let index = BlockIndex::build(include_bytes!("samplefiles/sample2.bz2").as_ref()).unwrap();
+    let second_block = index.blocks()[1].uncompressed_offset() as usize;
+    assert!(out.starts_with(sample1));
+    assert!(out.ends_with(&sample2[second_block..]));
+
+    let errors = reader.skipped_errors();
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(errors[0], DecoderError::Block(_)));
+    assert_eq!(
+        errors[0].location(),
+        Some(Location {
+            bit_offset: second_stream + 32,
+            block_index: 0,
+        })
+    );
+}
+
+fn limit_exceeded(err: io::Error) -> Limit {
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+    match err
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<DecoderError>()
+        .unwrap()
+    {
+        DecoderError::LimitExceeded(limit) => *limit,
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn limits() {
+    let sample1 = include_bytes!("samplefiles/sample1.bz2");
+    let sample2 = include_bytes!("samplefiles/sample2.bz2");
+    let mut sample1_sample3 = sample1.to_vec();
+    sample1_sample3.extend_from_slice(include_bytes!("samplefiles/sample3.bz2"));
+
+    let read = |compressed: &[u8], limits: Limits| {
+        let mut reader = DecoderReader::new(compressed);
+        reader.set_limits(limits);
+
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).map(|_| out)
+    };
+
+    let out = read(sample1, Limits::new().max_uncompressed_len(98696)).unwrap();
+    assert_eq!(out.len(), 98696);
+    let err = read(sample1, Limits::new().max_uncompressed_len(98695)).unwrap_err();
+    assert_eq!(limit_exceeded(err), Limit::UncompressedLen);
+
+    read(sample2, Limits::new().max_blocks(2)).unwrap();
+    let err = read(sample2, Limits::new().max_blocks(1)).unwrap_err();
+    assert_eq!(limit_exceeded(err), Limit::Blocks);
+
+    read(&sample1_sample3, Limits::new().max_streams(2)).unwrap();
+    let err = read(&sample1_sample3, Limits::new().max_streams(1)).unwrap_err();
+    assert_eq!(limit_exceeded(err), Limit::Streams);
+}
+
+#[test]
+fn options() {
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample3.bz2"));
+    let decompressed = include_bytes!("samplefiles/sample1.ref");
+
+    let options = DecoderOptions::new()
+        .multi_stream(false)
+        .limits(Limits::new().max_blocks(1))
+        .input_buf_len(7);
+    let mut reader = DecoderReader::with_options(compressed.as_slice(), options);
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, decompressed.as_ref());
+}
+
+#[test]
+fn no_verify_crc() {
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    let decompressed = include_bytes!("samplefiles/sample1.ref");
+
+    // corrupt both the block crc and the stream crc
+    compressed[10] ^= 0xff;
+    let crc_byte = compressed.len() - 2;
+    compressed[crc_byte] ^= 0xff;
+
+    let options = DecoderOptions::new().verify_crc(false);
+    let mut reader = DecoderReader::with_options(compressed.as_slice(), options);
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, decompressed.as_ref());
+}
+
+#[test]
+fn buffer_pool() {
+    let pool = BufferPool::new();
+
+    // the blocksize of every file is different from the previous one
+    let files: [(&[u8], &[u8]); 4] = [
+        (
+            include_bytes!("samplefiles/sample3.bz2"),
+            include_bytes!("samplefiles/sample3.ref"),
+        ),
+        (
+            include_bytes!("samplefiles/sample1.bz2"),
+            include_bytes!("samplefiles/sample1.ref"),
+        ),
+        (
+            include_bytes!("samplefiles/sample2.bz2"),
+            include_bytes!("samplefiles/sample2.ref"),
+        ),
+        (
+            include_bytes!("samplefiles/sample3.bz2"),
+            include_bytes!("samplefiles/sample3.ref"),
+        ),
+    ];
+    for &(compressed, decompressed) in files.iter() {
+        let mut reader = DecoderReader::new(compressed);
+        reader.set_buffer_pool(pool.clone());
+
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, decompressed);
+    }
+}
+
+#[test]
+fn reset() {
+    let sample1 = include_bytes!("samplefiles/sample1.bz2");
+    let sample3 = include_bytes!("samplefiles/sample3.bz2");
+    let mut truncated = sample1.to_vec();
+    truncated.truncate(1000);
+
+    let mut reader = DecoderReader::new(sample1.as_ref());
+
+    // stop in the middle of the first file
+    let mut buf = [0; 1024];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(
+        buf.as_ref(),
+        &include_bytes!("samplefiles/sample1.ref")[..1024]
+    );
+
+    let previous = reader.reset(sample3.as_ref());
+    assert!(previous.len() < sample1.len());
+    assert_eq!(reader.get_ref().len(), sample3.len());
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, include_bytes!("samplefiles/sample3.ref").as_ref());
+
+    // reset after an error
+    reader.reset(truncated.as_slice());
+    let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+    reader.reset(sample1.as_ref());
+    out.clear();
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, include_bytes!("samplefiles/sample1.ref").as_ref());
+    assert!(reader.into_inner().is_empty());
+}
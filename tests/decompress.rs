@@ -0,0 +1,173 @@
+use std::io::Read;
+#[cfg(not(feature = "rayon"))]
+use std::num::NonZeroUsize;
+#[cfg(not(feature = "rayon"))]
+use std::thread;
+
+use bzip2_rs::decoder::block::BlockErrorKind;
+use bzip2_rs::decoder::{BlockIndex, DecoderError};
+use bzip2_rs::header::HeaderError;
+#[cfg(feature = "rayon")]
+use bzip2_rs::RayonThreadPool;
+#[cfg(not(feature = "rayon"))]
+use bzip2_rs::ThreadPool;
+use bzip2_rs::{decompress, decompress_into, decompress_parallel, DecoderReader};
+
+#[cfg(not(feature = "rayon"))]
+struct NaiveThreadPool;
+
+#[cfg(not(feature = "rayon"))]
+impl ThreadPool for NaiveThreadPool {
+    fn spawn<F>(&self, func: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        thread::spawn(func);
+    }
+
+    fn max_threads(&self) -> NonZeroUsize {
+        NonZeroUsize::new(4).unwrap()
+    }
+}
+
+#[cfg(feature = "rayon")]
+fn new_pool() -> RayonThreadPool {
+    RayonThreadPool
+}
+
+#[cfg(not(feature = "rayon"))]
+fn new_pool() -> NaiveThreadPool {
+    NaiveThreadPool
+}
+
+/// The error returned by [`DecoderReader`] for `compressed`
+fn reader_error(compressed: &[u8]) -> DecoderError {
+    let err = DecoderReader::new(compressed)
+        .read_to_end(&mut Vec::new())
+        .unwrap_err();
+    err.into_inner()
+        .unwrap()
+        .downcast::<DecoderError>()
+        .map(|err| *err)
+        .unwrap()
+}
+
+#[test]
+fn samples() {
+    let samples: [(&[u8], &[u8]); 3] = [
+        (
+            include_bytes!("samplefiles/sample1.bz2"),
+            include_bytes!("samplefiles/sample1.ref"),
+        ),
+        (
+            include_bytes!("samplefiles/sample2.bz2"),
+            include_bytes!("samplefiles/sample2.ref"),
+        ),
+        (
+            include_bytes!("samplefiles/sample3.bz2"),
+            include_bytes!("samplefiles/sample3.ref"),
+        ),
+    ];
+
+    for &(compressed, decompressed) in samples.iter() {
+        assert_eq!(decompress(compressed).unwrap(), decompressed);
+        assert_eq!(
+            decompress_parallel(compressed, new_pool()).unwrap(),
+            decompressed
+        );
+    }
+}
+
+#[test]
+fn multiple_streams() {
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample2.bz2"));
+    compressed.extend_from_slice(b"trailing garbage");
+    let mut decompressed = include_bytes!("samplefiles/sample1.ref").to_vec();
+    decompressed.extend_from_slice(include_bytes!("samplefiles/sample2.ref"));
+
+    assert_eq!(decompress(&compressed).unwrap(), decompressed);
+    assert_eq!(
+        decompress_parallel(&compressed, new_pool()).unwrap(),
+        decompressed
+    );
+}
+
+#[test]
+fn empty() {
+    let err = DecoderError::Header(HeaderError::InvalidSignature);
+    assert_eq!(decompress(&[]).unwrap_err(), err);
+    assert_eq!(decompress(b"BZ").unwrap_err(), err);
+    assert_eq!(decompress_parallel(&[], new_pool()).unwrap_err(), err);
+
+    // a stream without blocks
+    let compressed = [66, 90, 104, 51, 23, 114, 69, 56, 80, 144, 0, 0, 0, 0];
+    assert!(decompress(&compressed).unwrap().is_empty());
+    assert_eq!(decompress_into(&compressed, &mut []), Ok(0));
+    assert!(decompress_parallel(&compressed, new_pool())
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn errors() {
+    let compressed = include_bytes!("samplefiles/sample2.bz2");
+
+    // damage the crc of the second block
+    let mut bad_crc = compressed.to_vec();
+    let index = BlockIndex::build(compressed.as_ref()).unwrap();
+    let second_block = index.blocks()[1].compressed_bit_offset();
+    let crc_byte = ((second_block + 48) / 8) as usize;
+    bad_crc[crc_byte] ^= 0xff;
+
+    let err = decompress(&bad_crc).unwrap_err();
+    assert_eq!(err, reader_error(&bad_crc));
+    assert_eq!(err.location().unwrap().block_index, 1);
+    assert_eq!(
+        err,
+        decompress_into(&bad_crc, &mut vec![0; 300_000]).unwrap_err()
+    );
+
+    let truncated = &compressed[..compressed.len() - 1000];
+    let err = decompress(truncated).unwrap_err();
+    assert_eq!(err, reader_error(truncated));
+    match err {
+        DecoderError::Block(err) => {
+            assert!(matches!(err.kind(), BlockErrorKind::Truncated(_)))
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+
+    // damage the stream crc
+    let mut bad_stream_crc = compressed.to_vec();
+    let crc_byte = bad_stream_crc.len() - 2;
+    bad_stream_crc[crc_byte] ^= 0xff;
+
+    let err = decompress(&bad_stream_crc).unwrap_err();
+    assert_eq!(err, reader_error(&bad_stream_crc));
+    assert!(matches!(err, DecoderError::BadStreamCrc { .. }));
+}
+
+#[test]
+fn into() {
+    let compressed = include_bytes!("samplefiles/sample2.bz2");
+    let decompressed = include_bytes!("samplefiles/sample2.ref");
+
+    let mut buf = vec![0; decompressed.len() + 10];
+    assert_eq!(
+        decompress_into(compressed, &mut buf),
+        Ok(decompressed.len())
+    );
+    assert_eq!(&buf[..decompressed.len()], decompressed.as_ref());
+
+    let buf = &mut buf[..decompressed.len()];
+    assert_eq!(decompress_into(compressed, buf), Ok(decompressed.len()));
+    assert_eq!(buf, decompressed.as_ref());
+
+    for &len in [0, 1000, decompressed.len() - 1].iter() {
+        assert_eq!(
+            decompress_into(compressed, &mut buf[..len]),
+            Err(DecoderError::BufferTooSmall)
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use std::io::{self, Read};
+
+use bzip2_rs::header::Header;
+use bzip2_rs::{DecoderReader, EncoderReader};
+
+fn roundtrip(decompressed: &[u8], header: Header) {
+    let mut reader = EncoderReader::new(decompressed, header);
+
+    let mut compressed = Vec::new();
+    reader.read_to_end(&mut compressed).unwrap();
+
+    let mut reader = DecoderReader::new(compressed.as_slice());
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+
+    assert_eq!(decompressed.len(), out.len());
+    assert_eq!(decompressed, out.as_slice());
+}
+
+#[test]
+fn empty() {
+    let decompressed: &[u8] = &[];
+    let mut reader = EncoderReader::new(decompressed, Header::from_raw_blocksize(3).unwrap());
+
+    let mut compressed = Vec::new();
+    reader.read_to_end(&mut compressed).unwrap();
+
+    assert_eq!(
+        compressed,
+        [66, 90, 104, 51, 23, 114, 69, 56, 80, 144, 0, 0, 0, 0]
+    );
+}
+
+#[test]
+fn small_reads() {
+    let decompressed = include_bytes!("samplefiles/sample3.ref");
+    let mut reader = EncoderReader::new(
+        decompressed.as_ref(),
+        Header::from_raw_blocksize(9).unwrap(),
+    );
+
+    let mut compressed = Vec::new();
+    let mut buf = [0; 7];
+    loop {
+        match reader.read(&mut buf).unwrap() {
+            0 => break,
+            n => compressed.extend_from_slice(&buf[..n]),
+        }
+    }
+
+    let mut out = Vec::new();
+    io::copy(&mut DecoderReader::new(compressed.as_slice()), &mut out).unwrap();
+    assert_eq!(decompressed.as_ref(), out.as_slice());
+}
+
+#[test]
+fn sample1() {
+    let decompressed = include_bytes!("samplefiles/sample1.ref");
+    roundtrip(decompressed, Header::from_raw_blocksize(9).unwrap());
+}
+
+#[test]
+fn sample2() {
+    let decompressed = include_bytes!("samplefiles/sample2.ref");
+    roundtrip(decompressed, Header::from_raw_blocksize(1).unwrap());
+}
+
+#[test]
+fn sample3() {
+    let decompressed = include_bytes!("samplefiles/sample3.ref");
+    roundtrip(decompressed, Header::from_raw_blocksize(5).unwrap());
+}
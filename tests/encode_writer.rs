@@ -0,0 +1,146 @@
+use std::io::{self, Read, Write};
+
+use bzip2_rs::header::Header;
+use bzip2_rs::{DecoderReader, EncoderWriter};
+
+use self::common::FailingWriter;
+
+mod common;
+
+fn decompress(compressed: &[u8]) -> Vec<u8> {
+    let mut reader = DecoderReader::new(compressed);
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    out
+}
+
+#[test]
+fn empty() {
+    let writer = EncoderWriter::new(Vec::new(), Header::from_raw_blocksize(3).unwrap());
+    let compressed = writer.finish().unwrap();
+
+    assert_eq!(
+        compressed,
+        [66, 90, 104, 51, 23, 114, 69, 56, 80, 144, 0, 0, 0, 0]
+    );
+}
+
+#[test]
+fn write_after_finish() {
+    let mut writer = EncoderWriter::new(Vec::new(), Header::from_raw_blocksize(9).unwrap());
+    writer.try_finish().unwrap();
+
+    let err = writer.write(b"hello").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+}
+
+#[test]
+fn write_after_failed_finish() {
+    let decompressed = include_bytes!("samplefiles/sample1.ref");
+
+    // only the stream header is written before finishing
+    let inner = FailingWriter {
+        out: Vec::new(),
+        fail_after: 1,
+        failures: 1,
+    };
+    let mut writer = EncoderWriter::new(inner, Header::from_raw_blocksize(9).unwrap());
+    writer.write_all(decompressed).unwrap();
+    writer.try_finish().unwrap_err();
+
+    let err = writer.write(b"hello").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+
+    let inner = writer.finish().unwrap();
+    assert_eq!(decompress(&inner.out), decompressed.as_ref());
+}
+
+#[test]
+fn drop_finishes() {
+    let decompressed = include_bytes!("samplefiles/sample3.ref");
+
+    let mut compressed = Vec::new();
+    {
+        let mut writer =
+            EncoderWriter::new(&mut compressed, Header::from_raw_blocksize(9).unwrap());
+        writer.write_all(decompressed).unwrap();
+    }
+
+    assert_eq!(decompress(&compressed), decompressed.as_ref());
+}
+
+#[test]
+fn partial_writes() {
+    let decompressed = include_bytes!("samplefiles/sample2.ref");
+
+    let mut writer = EncoderWriter::new(Vec::new(), Header::from_raw_blocksize(1).unwrap());
+    // at most a block is accepted at once
+    assert_eq!(writer.write(decompressed).unwrap(), 100_000);
+    writer.write_all(&decompressed[100_000..]).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    assert_eq!(decompress(&compressed), decompressed.as_ref());
+}
+
+#[test]
+fn sample1() {
+    let decompressed = include_bytes!("samplefiles/sample1.ref");
+
+    let mut writer = EncoderWriter::new(Vec::new(), Header::from_raw_blocksize(9).unwrap());
+    io::copy(&mut decompressed.as_ref(), &mut writer).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    assert_eq!(decompress(&compressed), decompressed.as_ref());
+}
+
+#[test]
+fn sample2() {
+    let decompressed = include_bytes!("samplefiles/sample2.ref");
+
+    let mut writer = EncoderWriter::new(Vec::new(), Header::from_raw_blocksize(1).unwrap());
+    io::copy(&mut decompressed.as_ref(), &mut writer).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    assert_eq!(decompress(&compressed), decompressed.as_ref());
+}
+
+#[test]
+fn sample3() {
+    let decompressed = include_bytes!("samplefiles/sample3.ref");
+
+    let mut writer = EncoderWriter::new(Vec::new(), Header::from_raw_blocksize(9).unwrap());
+    io::copy(&mut decompressed.as_ref(), &mut writer).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    assert_eq!(decompress(&compressed), decompressed.as_ref());
+}
+
+#[test]
+fn retry_after_error() {
+    let decompressed = include_bytes!("samplefiles/sample2.ref");
+
+    for &failures in [1, 2, 3].iter() {
+        let inner = FailingWriter {
+            out: Vec::new(),
+            fail_after: 5,
+            failures,
+        };
+        let mut writer = EncoderWriter::new(inner, Header::from_raw_blocksize(1).unwrap());
+
+        // the first failure happens while writing the data compressed
+        // after accepting a chunk, so it's only reported by the next write
+        let mut errors = 0;
+        for chunk in decompressed.chunks(10_000) {
+            while let Err(err) = writer.write_all(chunk) {
+                assert_eq!(err.kind(), io::ErrorKind::Other);
+                errors += 1;
+            }
+        }
+        assert_eq!(errors, failures - 1);
+
+        let inner = writer.finish().unwrap();
+        assert_eq!(inner.failures, 0);
+        assert_eq!(decompress(&inner.out), decompressed.as_ref());
+    }
+}
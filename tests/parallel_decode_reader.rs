@@ -1,10 +1,18 @@
 use std::io::{self, Read};
 use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::thread;
 
+use bzip2_rs::decoder::block::{BlockErrorKind, Field, Location};
+use bzip2_rs::decoder::{
+    verify_reader, BlockIndex, BufferPool, DecoderError, DecoderOptions, DecoderReader, Limit,
+    Limits, ParallelDecoder, ReadState,
+};
+use bzip2_rs::header::Header;
 #[cfg(feature = "rayon")]
 use bzip2_rs::RayonThreadPool;
-use bzip2_rs::{ParallelDecoderReader, ThreadPool};
+use bzip2_rs::{EncoderReader, ParallelDecoderReader, ThreadPool};
 
 #[cfg(not(feature = "rayon"))]
 struct NaiveThreadPool;
@@ -94,3 +102,476 @@ fn sample3() {
     assert_eq!(decompressed.len(), out.len());
     assert_eq!(decompressed.as_ref(), out.as_slice());
 }
+
+#[test]
+fn sample2_randomised() {
+    let compressed = include_bytes!("samplefiles/sample2_randomised.bz2");
+    let decompressed = include_bytes!("samplefiles/sample2.ref");
+
+    let mut reader = ParallelDecoderReader::new(compressed.as_ref(), new_pool(), usize::MAX);
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+
+    assert_eq!(decompressed.len(), out.len());
+    assert_eq!(decompressed.as_ref(), out.as_slice());
+}
+
+#[test]
+fn sample3_randomised() {
+    let compressed = include_bytes!("samplefiles/sample3_randomised.bz2");
+    let decompressed = include_bytes!("samplefiles/sample3.ref");
+
+    let mut reader = ParallelDecoderReader::new(compressed.as_ref(), new_pool(), usize::MAX);
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+
+    assert_eq!(decompressed.len(), out.len());
+    assert_eq!(decompressed.as_ref(), out.as_slice());
+}
+
+#[test]
+fn multiple_streams() {
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample3.bz2"));
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample2.bz2"));
+
+    let mut decompressed = include_bytes!("samplefiles/sample1.ref").to_vec();
+    decompressed.extend_from_slice(include_bytes!("samplefiles/sample3.ref"));
+    decompressed.extend_from_slice(include_bytes!("samplefiles/sample2.ref"));
+
+    let mut reader = ParallelDecoderReader::new(compressed.as_slice(), new_pool(), usize::MAX);
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+
+    assert_eq!(decompressed.len(), out.len());
+    assert_eq!(decompressed, out);
+}
+
+#[test]
+fn many_streams() {
+    const EMPTY_STREAM: &[u8] = &[66, 90, 104, 51, 23, 114, 69, 56, 80, 144, 0, 0, 0, 0];
+
+    let mut compressed = EMPTY_STREAM.repeat(20_000);
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample1.bz2"));
+    compressed.extend_from_slice(&EMPTY_STREAM.repeat(20_000));
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample3.bz2"));
+
+    let mut decompressed = include_bytes!("samplefiles/sample1.ref").to_vec();
+    decompressed.extend_from_slice(include_bytes!("samplefiles/sample3.ref"));
+
+    let mut reader = ParallelDecoderReader::new(compressed.as_slice(), new_pool(), usize::MAX);
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+
+    assert_eq!(decompressed.len(), out.len());
+    assert_eq!(decompressed, out);
+}
+
+#[test]
+fn single_stream() {
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample3.bz2"));
+    let decompressed = include_bytes!("samplefiles/sample1.ref");
+
+    let mut reader = ParallelDecoderReader::new(compressed.as_slice(), new_pool(), usize::MAX);
+    reader.set_multi_stream(false);
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+
+    assert_eq!(decompressed.len(), out.len());
+    assert_eq!(decompressed.as_ref(), out.as_slice());
+}
+
+#[test]
+fn trailing_garbage() {
+    let mut compressed = include_bytes!("samplefiles/sample3.bz2").to_vec();
+    compressed.extend_from_slice(b"this is not a bzip2 stream");
+    let decompressed = include_bytes!("samplefiles/sample3.ref");
+
+    let mut reader = ParallelDecoderReader::new(compressed.as_slice(), new_pool(), usize::MAX);
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+
+    assert_eq!(decompressed.len(), out.len());
+    assert_eq!(decompressed.as_ref(), out.as_slice());
+}
+
+#[test]
+fn bad_stream_crc() {
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    // the second to last byte is always part of the stream crc
+    let crc_byte = compressed.len() - 2;
+    compressed[crc_byte] ^= 0xff;
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample3.bz2"));
+
+    let mut reader = ParallelDecoderReader::new(compressed.as_slice(), new_pool(), usize::MAX);
+
+    let mut out = Vec::new();
+    let err = reader.read_to_end(&mut out).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().starts_with("bad stream crc"));
+}
+
+#[test]
+fn error_location() {
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    let second_stream = compressed.len() as u64 * 8;
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample2.bz2"));
+
+    // corrupt the crc of the second block of sample2,
+    // whose magic starts 544888 bits into the stream
+    let second_block = second_stream + 544888;
+    compressed[((second_block + 48) / 8) as usize] ^= 0x08;
+
+    let mut reader = ParallelDecoderReader::new(compressed.as_slice(), new_pool(), usize::MAX);
+
+    let mut out = Vec::new();
+    let err = reader.read_to_end(&mut out).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let err = err
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<DecoderError>()
+        .unwrap();
+    match err {
+        DecoderError::Block(err) => assert!(matches!(err.kind(), BlockErrorKind::BadCrc { .. })),
+        err => panic!("unexpected error: {}", err),
+    }
+    assert_eq!(
+        err.location(),
+        Some(Location {
+            bit_offset: second_block,
+            block_index: 1,
+        })
+    );
+}
+
+#[test]
+fn truncated() {
+    let compressed = include_bytes!("samplefiles/sample1.bz2");
+    let compressed = &compressed[..compressed.len() / 2];
+
+    let mut reader = ParallelDecoderReader::new(compressed, new_pool(), usize::MAX);
+
+    let mut out = Vec::new();
+    let err = reader.read_to_end(&mut out).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    let err = err
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<DecoderError>()
+        .unwrap();
+    match err {
+        DecoderError::Block(err) => assert!(matches!(err.kind(), BlockErrorKind::Truncated(_))),
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn truncated_before_stream_end() {
+    let compressed = include_bytes!("samplefiles/sample2.bz2");
+    // cut off the end of stream marker, keeping all of the blocks
+    let compressed = &compressed[..compressed.len() - 10];
+
+    let mut reader = ParallelDecoderReader::new(compressed, new_pool(), usize::MAX);
+
+    let mut out = Vec::new();
+    let err = reader.read_to_end(&mut out).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    let err = err
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<DecoderError>()
+        .unwrap();
+    match err {
+        DecoderError::Block(err) => {
+            assert_eq!(err.kind(), BlockErrorKind::Truncated(Field::Magic))
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn verify() {
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample2.bz2"));
+
+    let mut decoder = ParallelDecoder::new(new_pool(), 1024 * 1024);
+    let mut chunks = compressed.chunks(8192);
+    loop {
+        match decoder.verify().unwrap() {
+            ReadState::NeedsWrite => decoder.write(chunks.next().unwrap_or_default()).unwrap(),
+            ReadState::Read(_) => unreachable!(),
+            ReadState::Eof => break,
+        }
+    }
+
+    let summary = decoder.summary();
+    assert_eq!(summary.blocks(), 3);
+    assert_eq!(summary.uncompressed_len(), 98696 + 212340);
+    assert_eq!(summary, &verify_reader(compressed.as_slice()).unwrap());
+}
+
+#[test]
+fn skip_corrupt_blocks() {
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    let second_stream = compressed.len() as u64 * 8;
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample2.bz2"));
+    let sample1 = include_bytes!("samplefiles/sample1.ref");
+    let sample2 = include_bytes!("samplefiles/sample2.ref");
+
+    // damage the first block of sample2
+    compressed[(second_stream / 8) as usize + 1000] ^= 0xff;
+
+    let mut reader = ParallelDecoderReader::new(compressed.as_slice(), new_pool(), usize::MAX);
+    reader.set_skip_corrupt_blocks(true);
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+
+    // only the second block of sample2 is left after sample1
+    let index = BlockIndex::build(include_bytes!("samplefiles/sample2.bz2").as_ref()).unwrap();
+    let second_block = index.blocks()[1].uncompressed_offset() as usize;
+    assert!(out.starts_with(sample1));
+    assert!(out.ends_with(&sample2[second_block..]));
+
+    let errors = reader.skipped_errors();
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(errors[0], DecoderError::Block(_)));
+    assert_eq!(
+        errors[0].location(),
+        Some(Location {
+            bit_offset: second_stream + 32,
+            block_index: 0,
+        })
+    );
+}
+
+fn limit_exceeded(err: io::Error) -> Limit {
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+    match err
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<DecoderError>()
+        .unwrap()
+    {
+        DecoderError::LimitExceeded(limit) => *limit,
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn limits() {
+    let sample1 = include_bytes!("samplefiles/sample1.bz2");
+    let sample2 = include_bytes!("samplefiles/sample2.bz2");
+    let mut sample1_sample3 = sample1.to_vec();
+    sample1_sample3.extend_from_slice(include_bytes!("samplefiles/sample3.bz2"));
+
+    let read = |compressed: &[u8], limits: Limits| {
+        let mut reader = ParallelDecoderReader::new(compressed, new_pool(), usize::MAX);
+        reader.set_limits(limits);
+
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).map(|_| out)
+    };
+
+    let out = read(sample1, Limits::new().max_uncompressed_len(98696)).unwrap();
+    assert_eq!(out.len(), 98696);
+    let err = read(sample1, Limits::new().max_uncompressed_len(98695)).unwrap_err();
+    assert_eq!(limit_exceeded(err), Limit::UncompressedLen);
+
+    read(sample2, Limits::new().max_blocks(2)).unwrap();
+    let err = read(sample2, Limits::new().max_blocks(1)).unwrap_err();
+    assert_eq!(limit_exceeded(err), Limit::Blocks);
+
+    read(&sample1_sample3, Limits::new().max_streams(2)).unwrap();
+    let err = read(&sample1_sample3, Limits::new().max_streams(1)).unwrap_err();
+    assert_eq!(limit_exceeded(err), Limit::Streams);
+
+    read(sample2, Limits::new().max_buffered_memory(1024 * 1024)).unwrap();
+    let err = read(sample2, Limits::new().max_buffered_memory(1000)).unwrap_err();
+    assert_eq!(limit_exceeded(err), Limit::BufferedMemory);
+}
+
+/// A single threaded [`ThreadPool`] running the tasks as soon as they are spawned,
+/// which counts them
+#[derive(Clone, Default)]
+struct InlineThreadPool {
+    spawned: Arc<AtomicUsize>,
+}
+
+impl ThreadPool for InlineThreadPool {
+    fn spawn<F>(&self, func: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.spawned.fetch_add(1, Ordering::SeqCst);
+        func();
+    }
+
+    fn max_threads(&self) -> NonZeroUsize {
+        NonZeroUsize::new(1).unwrap()
+    }
+}
+
+#[test]
+fn max_buffered_memory() {
+    // about 10 blocks of 100k, which don't compress too well
+    let mut state = 0x2545_f491_u32;
+    let decompressed = (0..1_000_000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            b'a' + (state % 4) as u8
+        })
+        .collect::<Vec<u8>>();
+    let header = Header::from_raw_blocksize(1).unwrap();
+    let mut compressed = Vec::new();
+    EncoderReader::new(decompressed.as_slice(), header)
+        .read_to_end(&mut compressed)
+        .unwrap();
+
+    // leaves room for two blocks, including their 400 KB tt buffer
+    let max_buffered_memory = compressed.len() + 1024 * 1024;
+    let max_blocks = 2;
+
+    let pool = InlineThreadPool::default();
+    let spawned = Arc::clone(&pool.spawned);
+    let options =
+        DecoderOptions::new().limits(Limits::new().max_buffered_memory(max_buffered_memory));
+    let mut decoder = ParallelDecoder::with_options(pool, options);
+
+    // every write spawns one task for finding the blocks, the others decode them
+    let write = |decoder: &mut ParallelDecoder<InlineThreadPool>, buf: &[u8]| {
+        decoder.write(buf).unwrap();
+        spawned.swap(0, Ordering::SeqCst) - 1
+    };
+    let mut in_flight = write(&mut decoder, &compressed) + write(&mut decoder, &[]);
+
+    let mut out = Vec::new();
+    let mut buf = [0; 8 * 1024];
+    let mut blocks = 0;
+    loop {
+        let state = decoder.read(&mut buf).unwrap();
+        if let ReadState::Read(n) = state {
+            out.extend_from_slice(&buf[..n]);
+            continue;
+        }
+
+        // every scheduled block has been read
+        assert!(in_flight > 0);
+        assert!(in_flight <= max_blocks, "{} blocks in flight", in_flight);
+        blocks += in_flight;
+
+        match state {
+            ReadState::NeedsWrite => in_flight = write(&mut decoder, &[]),
+            _ => break,
+        }
+    }
+
+    assert!(blocks >= 10);
+    assert_eq!(blocks as u64, decoder.summary().blocks());
+    assert_eq!(decompressed, out);
+}
+
+#[test]
+fn options() {
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample3.bz2"));
+    let decompressed = include_bytes!("samplefiles/sample1.ref");
+
+    let options = DecoderOptions::new()
+        .multi_stream(false)
+        .limits(Limits::new().max_blocks(1))
+        .input_buf_len(7)
+        .max_preread_len(4096);
+    let mut reader =
+        ParallelDecoderReader::with_options(compressed.as_slice(), new_pool(), options);
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, decompressed.as_ref());
+}
+
+#[test]
+fn no_verify_crc() {
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    let decompressed = include_bytes!("samplefiles/sample1.ref");
+
+    // corrupt both the block crc and the stream crc
+    compressed[10] ^= 0xff;
+    let crc_byte = compressed.len() - 2;
+    compressed[crc_byte] ^= 0xff;
+
+    let options = DecoderOptions::new().verify_crc(false);
+    let mut reader =
+        ParallelDecoderReader::with_options(compressed.as_slice(), new_pool(), options);
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, decompressed.as_ref());
+}
+
+#[test]
+fn shared_buffer_pool() {
+    let pool = BufferPool::new();
+
+    let mut compressed = include_bytes!("samplefiles/sample2.bz2").to_vec();
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample1.bz2"));
+    let mut decompressed = include_bytes!("samplefiles/sample2.ref").to_vec();
+    decompressed.extend_from_slice(include_bytes!("samplefiles/sample1.ref"));
+
+    let threads = (0..4)
+        .map(|i| {
+            let pool = pool.clone();
+            let compressed = compressed.clone();
+            let decompressed = decompressed.clone();
+
+            thread::spawn(move || {
+                for _ in 0..3 {
+                    let mut out = Vec::new();
+                    if i % 2 == 0 {
+                        let options = DecoderOptions::new().max_preread_len(64 * 1024);
+                        let mut reader = ParallelDecoderReader::with_options(
+                            compressed.as_slice(),
+                            new_pool(),
+                            options,
+                        );
+                        reader.set_buffer_pool(pool.clone());
+                        reader.read_to_end(&mut out).unwrap();
+                    } else {
+                        let mut reader = DecoderReader::new(compressed.as_slice());
+                        reader.set_buffer_pool(pool.clone());
+                        reader.read_to_end(&mut out).unwrap();
+                    }
+                    assert_eq!(out, decompressed);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
+
+#[test]
+fn into_inner() {
+    let compressed = include_bytes!("samplefiles/sample1.bz2");
+    let mut reader = ParallelDecoderReader::new(compressed.as_ref(), new_pool(), usize::MAX);
+    assert_eq!(reader.get_ref().len(), compressed.len());
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, include_bytes!("samplefiles/sample1.ref").as_ref());
+
+    assert!(reader.get_mut().is_empty());
+    assert!(reader.into_inner().is_empty());
+}
@@ -0,0 +1,251 @@
+use std::io::{self, Read, Write};
+use std::num::NonZeroUsize;
+use std::thread;
+
+use bzip2_rs::encoder::EncoderError;
+use bzip2_rs::header::Header;
+#[cfg(feature = "rayon")]
+use bzip2_rs::RayonThreadPool;
+use bzip2_rs::{DecoderReader, ParallelEncoderWriter, ThreadPool};
+
+use self::common::FailingWriter;
+
+mod common;
+
+#[cfg(not(feature = "rayon"))]
+struct NaiveThreadPool;
+
+#[cfg(not(feature = "rayon"))]
+impl ThreadPool for NaiveThreadPool {
+    fn spawn<F>(&self, func: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        thread::spawn(func);
+    }
+
+    fn max_threads(&self) -> NonZeroUsize {
+        NonZeroUsize::new(4).unwrap()
+    }
+}
+
+#[cfg(feature = "rayon")]
+fn new_pool() -> RayonThreadPool {
+    RayonThreadPool
+}
+
+#[cfg(not(feature = "rayon"))]
+fn new_pool() -> NaiveThreadPool {
+    NaiveThreadPool
+}
+
+fn decompress(compressed: &[u8]) -> Vec<u8> {
+    let mut reader = DecoderReader::new(compressed);
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    out
+}
+
+#[test]
+fn empty() {
+    let writer = ParallelEncoderWriter::new(
+        Vec::new(),
+        Header::from_raw_blocksize(3).unwrap(),
+        new_pool(),
+        4,
+    );
+    let compressed = writer.finish().unwrap();
+
+    assert_eq!(
+        compressed,
+        [66, 90, 104, 51, 23, 114, 69, 56, 80, 144, 0, 0, 0, 0]
+    );
+}
+
+#[test]
+fn drop_finishes() {
+    let decompressed = include_bytes!("samplefiles/sample2.ref");
+
+    let mut compressed = Vec::new();
+    {
+        let mut writer = ParallelEncoderWriter::new(
+            &mut compressed,
+            Header::from_raw_blocksize(1).unwrap(),
+            new_pool(),
+            4,
+        );
+        writer.write_all(decompressed).unwrap();
+    }
+
+    assert_eq!(decompress(&compressed), decompressed.as_ref());
+}
+
+#[test]
+fn single_block_in_flight() {
+    let decompressed = include_bytes!("samplefiles/sample2.ref");
+
+    let mut writer = ParallelEncoderWriter::new(
+        Vec::new(),
+        Header::from_raw_blocksize(1).unwrap(),
+        new_pool(),
+        1,
+    );
+    for chunk in decompressed.chunks(1000) {
+        writer.write_all(chunk).unwrap();
+    }
+    let compressed = writer.finish().unwrap();
+
+    assert_eq!(decompress(&compressed), decompressed.as_ref());
+}
+
+#[test]
+fn same_output_as_single_threaded() {
+    let decompressed = include_bytes!("samplefiles/sample2.ref");
+    let header = Header::from_raw_blocksize(1).unwrap();
+
+    let mut writer = ParallelEncoderWriter::new(Vec::new(), header.clone(), new_pool(), 8);
+    io::copy(&mut decompressed.as_ref(), &mut writer).unwrap();
+    let parallel = writer.finish().unwrap();
+
+    let mut writer = bzip2_rs::EncoderWriter::new(Vec::new(), header);
+    io::copy(&mut decompressed.as_ref(), &mut writer).unwrap();
+    let single = writer.finish().unwrap();
+
+    assert_eq!(parallel, single);
+}
+
+#[test]
+fn partial_writes() {
+    let decompressed = include_bytes!("samplefiles/sample2.ref");
+
+    let mut writer = ParallelEncoderWriter::new(
+        Vec::new(),
+        Header::from_raw_blocksize(1).unwrap(),
+        new_pool(),
+        4,
+    );
+    // at most a block is accepted at once
+    assert_eq!(writer.write(decompressed).unwrap(), 100_000);
+    writer.write_all(&decompressed[100_000..]).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    assert_eq!(decompress(&compressed), decompressed.as_ref());
+}
+
+#[test]
+fn sample1() {
+    let decompressed = include_bytes!("samplefiles/sample1.ref");
+
+    let mut writer = ParallelEncoderWriter::new(
+        Vec::new(),
+        Header::from_raw_blocksize(9).unwrap(),
+        new_pool(),
+        4,
+    );
+    io::copy(&mut decompressed.as_ref(), &mut writer).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    assert_eq!(decompress(&compressed), decompressed.as_ref());
+}
+
+#[test]
+fn sample3() {
+    let decompressed = include_bytes!("samplefiles/sample3.ref");
+
+    let mut writer = ParallelEncoderWriter::new(
+        Vec::new(),
+        Header::from_raw_blocksize(1).unwrap(),
+        new_pool(),
+        4,
+    );
+    io::copy(&mut decompressed.as_ref(), &mut writer).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    assert_eq!(decompress(&compressed), decompressed.as_ref());
+}
+
+/// A threadpool whose tasks fail without compressing their block
+enum FailingThreadPool {
+    /// The task is dropped without being run
+    Drop,
+    /// The thread running the task panics
+    Panic,
+}
+
+impl ThreadPool for FailingThreadPool {
+    fn spawn<F>(&self, func: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        match self {
+            FailingThreadPool::Drop => drop(func),
+            FailingThreadPool::Panic => {
+                thread::spawn(move || {
+                    let _func = func;
+                    panic!("the task panicked");
+                });
+            }
+        }
+    }
+
+    fn max_threads(&self) -> NonZeroUsize {
+        NonZeroUsize::new(4).unwrap()
+    }
+}
+
+#[test]
+fn failed_task() {
+    let decompressed = include_bytes!("samplefiles/sample2.ref");
+
+    for pool in [FailingThreadPool::Drop, FailingThreadPool::Panic] {
+        let mut writer =
+            ParallelEncoderWriter::new(Vec::new(), Header::from_raw_blocksize(1).unwrap(), pool, 4);
+
+        // the failure is reported by the first call finding out about it,
+        // and by every one after it
+        let err = writer
+            .write_all(decompressed)
+            .and_then(|()| writer.try_finish())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(
+            err.get_ref().unwrap().downcast_ref::<EncoderError>(),
+            Some(&EncoderError::BlockTaskFailed)
+        );
+
+        let err = writer.flush().unwrap_err();
+        assert_eq!(
+            err.get_ref().unwrap().downcast_ref::<EncoderError>(),
+            Some(&EncoderError::BlockTaskFailed)
+        );
+    }
+}
+
+#[test]
+fn retry_after_error() {
+    let decompressed = include_bytes!("samplefiles/sample2.ref");
+
+    for &failures in [1, 2, 3].iter() {
+        let inner = FailingWriter {
+            out: Vec::new(),
+            fail_after: 5,
+            failures,
+        };
+        let mut writer = ParallelEncoderWriter::new(
+            inner,
+            Header::from_raw_blocksize(1).unwrap(),
+            new_pool(),
+            1,
+        );
+
+        for chunk in decompressed.chunks(10_000) {
+            while writer.write_all(chunk).is_err() {}
+        }
+        while writer.try_finish().is_err() {}
+
+        let inner = writer.finish().unwrap();
+        assert_eq!(inner.failures, 0);
+        assert_eq!(decompress(&inner.out), decompressed.as_ref());
+    }
+}
@@ -0,0 +1,64 @@
+use std::io::Read;
+
+use bzip2_rs::decoder::recover_blocks;
+use bzip2_rs::DecoderReader;
+
+#[test]
+fn intact() {
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample2.bz2"));
+    let mut decompressed = include_bytes!("samplefiles/sample1.ref").to_vec();
+    decompressed.extend_from_slice(include_bytes!("samplefiles/sample2.ref"));
+
+    let blocks = recover_blocks(&compressed).collect::<Vec<_>>();
+    assert_eq!(blocks.len(), 3);
+    assert_eq!(blocks[0].bit_offset(), 32);
+    assert_eq!(blocks[2].bit_offset(), 32348 * 8 + 544888);
+
+    let recovered = blocks
+        .into_iter()
+        .flat_map(|block| block.into_data())
+        .collect::<Vec<_>>();
+    assert_eq!(recovered, decompressed);
+}
+
+#[test]
+fn damaged() {
+    let mut compressed = include_bytes!("samplefiles/sample2.bz2").to_vec();
+    let decompressed = include_bytes!("samplefiles/sample2.ref");
+
+    // damage the first block and drop the end of stream marker
+    compressed[1000] ^= 0xff;
+    compressed.truncate(compressed.len() - 10);
+
+    let blocks = recover_blocks(&compressed).collect::<Vec<_>>();
+    assert_eq!(blocks.len(), 1);
+
+    let block = &blocks[0];
+    assert_eq!(block.bit_offset(), 544888);
+    assert!(decompressed.ends_with(block.data()));
+
+    let mut stream = Vec::new();
+    block.write_stream(&mut stream).unwrap();
+
+    let mut reader = DecoderReader::new(stream.as_slice());
+    let mut recovered = Vec::new();
+    reader.read_to_end(&mut recovered).unwrap();
+    assert_eq!(recovered, block.data());
+}
+
+#[test]
+fn missing_header() {
+    let compressed = include_bytes!("samplefiles/sample3.bz2");
+    let decompressed = include_bytes!("samplefiles/sample3.ref");
+
+    // the first block starts at a byte boundary
+    let blocks = recover_blocks(&compressed[4..]).collect::<Vec<_>>();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].data(), decompressed.as_ref());
+
+    let mut stream = Vec::new();
+    blocks[0].write_stream(&mut stream).unwrap();
+    // the original stream, with a `BZh9` header instead of `BZh3`
+    assert_eq!(&stream[4..], &compressed[4..]);
+}
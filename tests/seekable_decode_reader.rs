@@ -0,0 +1,150 @@
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+
+use bzip2_rs::decoder::{BlockIndex, SeekableDecoderReader};
+
+fn read_at<R: Read + Seek>(reader: &mut R, pos: SeekFrom, len: usize) -> Vec<u8> {
+    reader.seek(pos).unwrap();
+
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn sample1() {
+    let compressed = include_bytes!("samplefiles/sample1.bz2");
+    let decompressed = include_bytes!("samplefiles/sample1.ref");
+
+    let mut reader = SeekableDecoderReader::new(Cursor::new(compressed)).unwrap();
+
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf).unwrap();
+    assert_eq!(&buf, decompressed);
+}
+
+#[test]
+fn sample2() {
+    let compressed = include_bytes!("samplefiles/sample2.bz2");
+    let decompressed = include_bytes!("samplefiles/sample2.ref");
+
+    let mut reader = SeekableDecoderReader::new(Cursor::new(compressed)).unwrap();
+
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf).unwrap();
+    assert_eq!(&buf, decompressed);
+}
+
+#[test]
+fn sample3() {
+    let compressed = include_bytes!("samplefiles/sample3.bz2");
+    let decompressed = include_bytes!("samplefiles/sample3.ref");
+
+    let mut reader = SeekableDecoderReader::new(Cursor::new(compressed)).unwrap();
+
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf).unwrap();
+    assert_eq!(&buf, decompressed);
+}
+
+#[test]
+fn seek() {
+    let compressed = include_bytes!("samplefiles/sample2.bz2");
+    let decompressed = include_bytes!("samplefiles/sample2.ref");
+
+    let mut reader = SeekableDecoderReader::new(Cursor::new(compressed)).unwrap();
+    let boundary = reader.index().blocks()[1].uncompressed_offset() as usize;
+
+    // backwards, across blocks and across the boundary between two blocks
+    for &start in &[200_000, 10, boundary - 100, 150_000, 0, boundary] {
+        let buf = read_at(&mut reader, SeekFrom::Start(start as u64), 1000);
+        assert_eq!(buf, &decompressed[start..start + 1000]);
+    }
+
+    let buf = read_at(&mut reader, SeekFrom::End(-500), 500);
+    assert_eq!(buf, &decompressed[decompressed.len() - 500..]);
+
+    reader.seek(SeekFrom::Start(1000)).unwrap();
+    let buf = read_at(&mut reader, SeekFrom::Current(-200), 200);
+    assert_eq!(buf, &decompressed[800..1000]);
+    assert_eq!(reader.stream_position().unwrap(), 1000);
+}
+
+#[test]
+fn seek_past_end() {
+    let compressed = include_bytes!("samplefiles/sample3.bz2");
+
+    let mut reader = SeekableDecoderReader::new(Cursor::new(compressed)).unwrap();
+    assert_eq!(reader.seek(SeekFrom::End(10)).unwrap(), 120244 + 10);
+
+    let mut buf = [0; 1024];
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn seek_negative() {
+    let compressed = include_bytes!("samplefiles/sample3.bz2");
+
+    let mut reader = SeekableDecoderReader::new(Cursor::new(compressed)).unwrap();
+    let err = reader.seek(SeekFrom::Current(-1)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn multiple_streams() {
+    let mut compressed = include_bytes!("samplefiles/sample1.bz2").to_vec();
+    compressed.extend_from_slice(include_bytes!("samplefiles/sample2.bz2"));
+    let mut decompressed = include_bytes!("samplefiles/sample1.ref").to_vec();
+    decompressed.extend_from_slice(include_bytes!("samplefiles/sample2.ref"));
+
+    let index = BlockIndex::build(compressed.as_slice()).unwrap();
+    assert_eq!(index.blocks().len(), 3);
+    assert_eq!(index.uncompressed_len(), decompressed.len() as u64);
+
+    let mut reader = SeekableDecoderReader::with_index(Cursor::new(compressed), index);
+
+    let start = 98696 - 50;
+    let buf = read_at(&mut reader, SeekFrom::Start(start as u64), 100);
+    assert_eq!(buf, &decompressed[start..start + 100]);
+
+    let buf = read_at(&mut reader, SeekFrom::Start(0), 100);
+    assert_eq!(buf, &decompressed[..100]);
+}
+
+#[test]
+fn serialized_index() {
+    let compressed = include_bytes!("samplefiles/sample2.bz2");
+    let decompressed = include_bytes!("samplefiles/sample2.ref");
+
+    let mut serialized = Vec::new();
+    BlockIndex::build(compressed.as_ref())
+        .unwrap()
+        .write_to(&mut serialized)
+        .unwrap();
+
+    let index = BlockIndex::read_from(serialized.as_slice()).unwrap();
+    let mut reader = SeekableDecoderReader::with_index(Cursor::new(compressed), index);
+
+    let buf = read_at(&mut reader, SeekFrom::Start(150_000), 1000);
+    assert_eq!(buf, &decompressed[150_000..151_000]);
+}
+
+#[test]
+fn mismatched_index() {
+    let compressed = include_bytes!("samplefiles/sample2.bz2");
+
+    let mut serialized = Vec::new();
+    BlockIndex::build(compressed.as_ref())
+        .unwrap()
+        .write_to(&mut serialized)
+        .unwrap();
+    // change the crc of the first block
+    serialized[14 + 12] ^= 1;
+
+    let index = BlockIndex::read_from(serialized.as_slice()).unwrap();
+    let mut reader = SeekableDecoderReader::with_index(Cursor::new(compressed), index);
+
+    let mut buf = [0; 1024];
+    let err = reader.read(&mut buf).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "the block index doesn't match the file");
+}